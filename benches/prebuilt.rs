@@ -0,0 +1,48 @@
+// Compares writing a prebuilt response with building and serializing the same
+// response every time. Run with `cargo bench --bench prebuilt`.
+
+use multi_threaded_web_server::response::HttpResponse;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 200_000;
+
+fn health() -> HttpResponse {
+    HttpResponse::json(200, "OK")
+        .with_header("Cache-Control", "no-store")
+        .with_body(r#"{"status": "up", "checks": {"database": "ok", "cache": "ok"}}"#.to_string())
+}
+
+// Writes each response into a reused buffer, as a connection would to its socket
+fn time(mut respond: impl FnMut() -> HttpResponse) -> Duration {
+    let mut out = Vec::with_capacity(1024);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        out.clear();
+        black_box(respond()).write_to(&mut out).unwrap();
+        black_box(&out);
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let per_second = f64::from(ITERATIONS) / elapsed.as_secs_f64();
+    println!("{name:>10}: {elapsed:>10.2?} for {ITERATIONS} responses, {per_second:>12.0} per second");
+}
+
+fn main() {
+    let prebuilt_bytes = Arc::new(health().to_bytes());
+    assert_eq!(HttpResponse::from_prebuilt(Arc::clone(&prebuilt_bytes)).to_bytes(), *prebuilt_bytes);
+
+    // Once first to warm up, then measured
+    for round in 0..2 {
+        let normal = time(health);
+        let prebuilt = time(|| HttpResponse::from_prebuilt(Arc::clone(&prebuilt_bytes)));
+        if round == 1 {
+            report("normal", normal);
+            report("prebuilt", prebuilt);
+            println!("  speedup: {:.1}x", normal.as_secs_f64() / prebuilt.as_secs_f64());
+        }
+    }
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use multi_threaded_web_server::http::HttpRequest;
+
+// Run with `cargo +nightly fuzz run parse` from the repository root
+fuzz_target!(|data: &[u8]| {
+    let _ = HttpRequest::parse_bytes(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = HttpRequest::parse(text);
+    }
+});
@@ -0,0 +1,187 @@
+#![allow(dead_code)]
+use crate::date::{format_clf_date, format_http_date};
+use crate::http::HttpRequest;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    // Apache Common Log Format, understood by most log tooling
+    Common,
+    // One JSON object per line
+    Json,
+    Plain,
+}
+
+// How much the server logs. Each level includes everything below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogLevel {
+    // Only server errors: failures and access lines for 5xx responses
+    Error,
+    // An access line for every response
+    #[default]
+    Access,
+    // Access lines plus the parsed headers of each request
+    Debug,
+}
+
+impl LogLevel {
+    pub fn logs_response(self, status: u16) -> bool {
+        self >= LogLevel::Access || status >= 500
+    }
+}
+
+pub struct AccessLogEntry<'a> {
+    pub peer: Option<IpAddr>,
+    pub time: SystemTime,
+    // None when the request couldn't be parsed
+    pub request: Option<&'a HttpRequest>,
+    pub status: u16,
+    // Bytes written to the client for the response, head and framing included
+    pub bytes: usize,
+}
+
+impl AccessLogEntry<'_> {
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let host = self
+            .peer
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let (method, path, version) = match self.request {
+            Some(request) => (
+                request.method.as_str(),
+                request.raw_target.as_str(),
+                request.version.as_str(),
+            ),
+            None => ("-", "-", "-"),
+        };
+
+        match format {
+            AccessLogFormat::Common => {
+                let request_line = match self.request {
+                    Some(_) => format!("{} {} {}", method, path, version),
+                    None => "-".to_string(),
+                };
+                let bytes = match self.bytes {
+                    0 => "-".to_string(),
+                    bytes => bytes.to_string(),
+                };
+                format!(
+                    "{} - - [{}] \"{}\" {} {}",
+                    host,
+                    format_clf_date(self.time),
+                    request_line,
+                    self.status,
+                    bytes
+                )
+            }
+            AccessLogFormat::Json => format!(
+                r#"{{"host": "{}", "time": "{}", "method": "{}", "path": "{}", "protocol": "{}", "status": {}, "bytes": {}}}"#,
+                host,
+                format_http_date(self.time),
+                method,
+                escape_json(path),
+                escape_json(version),
+                self.status,
+                self.bytes
+            ),
+            AccessLogFormat::Plain => format!(
+                "{} {} {} -> {} ({} bytes)",
+                host, method, path, self.status, self.bytes
+            ),
+        }
+    }
+}
+
+pub(crate) fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // host ident user [dd/Mon/yyyy:hh:mm:ss +zzzz] "request" status bytes
+    fn is_common_log_format(line: &str) -> bool {
+        let Some((prefix, rest)) = line.split_once(" [") else {
+            return false;
+        };
+        let Some((date, rest)) = rest.split_once("] \"") else {
+            return false;
+        };
+        let Some((request_line, rest)) = rest.rsplit_once("\" ") else {
+            return false;
+        };
+        let fields: Vec<&str> = prefix.split(' ').collect();
+        let tail: Vec<&str> = rest.split(' ').collect();
+        let date_ok = date.len() == 26
+            && date.as_bytes()[2] == b'/'
+            && date.as_bytes()[6] == b'/'
+            && date.ends_with(" +0000");
+        fields.len() == 3
+            && date_ok
+            && request_line.split(' ').count() == 3
+            && tail.len() == 2
+            && tail[0].len() == 3
+            && tail[0].bytes().all(|b| b.is_ascii_digit())
+            && (tail[1] == "-" || tail[1].bytes().all(|b| b.is_ascii_digit()))
+    }
+
+    #[test]
+    fn common_format_matches_clf() {
+        let request = RequestBuilder::get("/api/users?page=2").build();
+        let entry = AccessLogEntry {
+            peer: Some("127.0.0.1".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(971186136),
+            request: Some(&request),
+            status: 200,
+            bytes: 2326,
+        };
+        let line = entry.format(AccessLogFormat::Common);
+        assert_eq!(
+            line,
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /api/users?page=2 HTTP/1.1\" 200 2326"
+        );
+        assert!(is_common_log_format(&line));
+    }
+
+    #[test]
+    fn unparsed_request_still_logs_in_clf() {
+        let entry = AccessLogEntry {
+            peer: None,
+            time: UNIX_EPOCH,
+            request: None,
+            status: 400,
+            bytes: 0,
+        };
+        assert_eq!(
+            entry.format(AccessLogFormat::Common),
+            "- - - [01/Jan/1970:00:00:00 +0000] \"-\" 400 -"
+        );
+    }
+
+    #[test]
+    fn the_log_shows_the_target_as_sent() {
+        let request = RequestBuilder::get("/search%20results?q=a%2Bb").build();
+        let entry = AccessLogEntry {
+            peer: None,
+            time: UNIX_EPOCH,
+            request: Some(&request),
+            status: 200,
+            bytes: 0,
+        };
+        let line = entry.format(AccessLogFormat::Common);
+        assert!(line.contains("\"GET /search%20results?q=a%2Bb HTTP/1.1\""), "{line}");
+    }
+}
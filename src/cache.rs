@@ -0,0 +1,103 @@
+#![allow(dead_code)]
+use crate::response::HttpResponse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Thread-safe response cache holding at most `capacity` entries, each valid for `ttl`
+pub struct ResponseCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, (Instant, HttpResponse)>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        assert!(capacity > 0);
+
+        ResponseCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<HttpResponse> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((stored, response)) if stored.elapsed() < self.ttl => Some(response.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, key: String, response: HttpResponse) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (stored, _))| *stored)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key, (Instant::now(), response));
+    }
+
+    // The handler runs outside the lock, so two concurrent misses for the same
+    // key may both compute the response; the later one wins
+    pub fn get_or_insert_with<F>(&self, key: &str, compute: F) -> HttpResponse
+    where
+        F: FnOnce() -> HttpResponse,
+    {
+        if let Some(response) = self.get(key) {
+            return response;
+        }
+
+        let response = compute();
+        // Errors are not worth remembering, and a stream can only be sent once
+        if (200..300).contains(&response.status_code) && !response.is_streaming() {
+            self.insert(key.to_string(), response.clone());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let cache = ResponseCache::new(Duration::ZERO, 4);
+        cache.insert("a".to_string(), HttpResponse::ok());
+        assert!(cache.get("a").is_none());
+
+        let cache = ResponseCache::new(Duration::from_secs(60), 4);
+        cache.insert("a".to_string(), HttpResponse::ok());
+        assert!(cache.get("a").is_some());
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 4);
+        cache.get_or_insert_with("a", HttpResponse::internal_server_error);
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn full_cache_evicts_the_oldest_entry() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 2);
+        for key in ["a", "b", "c"] {
+            cache.insert(key.to_string(), HttpResponse::ok());
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some() && cache.get("c").is_some());
+    }
+}
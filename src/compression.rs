@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+use crate::http::HttpRequest;
+use crate::response::HttpResponse;
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{Read, Write};
+
+// Bodies smaller than this aren't worth the gzip header and CPU time
+const MIN_COMPRESS_BYTES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    // Least CPU per response, for latency-sensitive endpoints
+    Fast,
+    #[default]
+    Balanced,
+    // Smallest output, for large bodies that are sent often
+    Best,
+}
+
+impl CompressionLevel {
+    fn codec_level(self) -> Compression {
+        match self {
+            CompressionLevel::Fast => Compression::fast(),
+            CompressionLevel::Balanced => Compression::default(),
+            CompressionLevel::Best => Compression::best(),
+        }
+    }
+}
+
+pub fn gzip(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), level.codec_level());
+    // Writing to a Vec can't fail
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecompressError {
+    // The data isn't valid gzip
+    Invalid,
+    // Decompressing would exceed the limit, as a zip bomb would
+    TooLarge,
+}
+
+// Stops as soon as the output passes `limit` bytes instead of inflating it all
+pub fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>, DecompressError> {
+    let mut output = Vec::new();
+    MultiGzDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut output)
+        .map_err(|_| DecompressError::Invalid)?;
+    if output.len() > limit {
+        return Err(DecompressError::TooLarge);
+    }
+    Ok(output)
+}
+
+// Gzips the body when the client accepts it and the content is likely to
+// shrink: textual types only, nothing already encoded or streamed
+pub fn compress_response(
+    request: &HttpRequest,
+    response: HttpResponse,
+    level: CompressionLevel,
+) -> HttpResponse {
+    let compressible = response.body.len() >= MIN_COMPRESS_BYTES
+        && !response.is_streaming()
+        && !response.has_header("Content-Encoding")
+        && response.header("Content-Type").is_some_and(is_compressible_type);
+    if !compressible || !accepts_gzip(request) {
+        return response;
+    }
+
+    // The body now depends on Accept-Encoding, so caches must key on it
+    response
+        .with_header("Content-Encoding", "gzip")
+        .with_vary("Accept-Encoding")
+        .map_body(|body| gzip(&body, level))
+}
+
+fn is_compressible_type(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    media_type.starts_with("text/")
+        || matches!(
+            media_type,
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+pub fn accepts_gzip(request: &HttpRequest) -> bool {
+    let Some(accept_encoding) = request.headers.get("accept-encoding") else {
+        return false;
+    };
+
+    accept_encoding.split(',').any(|coding| {
+        let mut parts = coding.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        // "gzip;q=0" explicitly refuses gzip
+        let refused = parts.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_text() -> Vec<u8> {
+        (0..2000).map(|i| format!("line {} of {}\n", i, i % 7)).collect::<String>().into_bytes()
+    }
+
+    #[test]
+    fn every_level_round_trips() {
+        let text = sample_text();
+        for level in [CompressionLevel::Fast, CompressionLevel::Balanced, CompressionLevel::Best] {
+            let compressed = gzip(&text, level);
+            assert!(compressed.len() < text.len(), "{level:?}");
+            assert_eq!(gunzip(&compressed, text.len()).unwrap(), text, "{level:?}");
+        }
+    }
+
+    #[test]
+    fn levels_trade_size_for_speed() {
+        let text = sample_text();
+        let fast = gzip(&text, CompressionLevel::Fast).len();
+        let best = gzip(&text, CompressionLevel::Best).len();
+        assert!(best < fast, "best {best} should be smaller than fast {fast}");
+    }
+
+    #[test]
+    fn gunzip_stops_at_the_limit() {
+        let compressed = gzip(&sample_text(), CompressionLevel::Fast);
+        assert_eq!(gunzip(&compressed, 100), Err(DecompressError::TooLarge));
+        assert_eq!(gunzip(b"not gzip", 100), Err(DecompressError::Invalid));
+    }
+
+    fn gzip_request() -> HttpRequest {
+        crate::testing::RequestBuilder::get("/").header("Accept-Encoding", "gzip").build()
+    }
+
+    fn large_text() -> HttpResponse {
+        HttpResponse::text(String::from_utf8(sample_text()).unwrap())
+    }
+
+    #[test]
+    fn compressed_responses_vary_on_accept_encoding() {
+        let response = compress_response(&gzip_request(), large_text(), CompressionLevel::Fast);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    }
+
+    #[test]
+    fn an_existing_vary_is_merged_not_replaced() {
+        let response = large_text().with_header("Vary", "Origin");
+        let response = compress_response(&gzip_request(), response, CompressionLevel::Fast);
+        assert_eq!(response.header("Vary"), Some("Origin, Accept-Encoding"));
+
+        let response = large_text().with_header("Vary", "accept-encoding");
+        let response = compress_response(&gzip_request(), response, CompressionLevel::Fast);
+        assert_eq!(response.header("Vary"), Some("accept-encoding"));
+    }
+
+    #[test]
+    fn uncompressed_responses_are_left_alone() {
+        let request = crate::testing::RequestBuilder::get("/").build();
+        let response = compress_response(&request, large_text(), CompressionLevel::Fast);
+        assert_eq!(response.header("Content-Encoding"), None);
+    }
+}
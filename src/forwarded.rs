@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+use std::net::IpAddr;
+
+// A CIDR block such as "10.0.0.0/8" or "fd00::/8"; a bare address is a /32 or /128
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn parse(value: &str) -> Option<IpRange> {
+        let (address, prefix_len) = match value.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len.parse::<u8>().ok()?)),
+            None => (value.trim(), None),
+        };
+        let network: IpAddr = address.parse().ok()?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            return None;
+        }
+        Some(IpRange { network, prefix_len })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(network).into(), u32::from(ip).into(), self.prefix_len, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(network), u128::from(ip), self.prefix_len, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: u128, ip: u128, prefix_len: u8, bits: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = bits - prefix_len;
+    network >> shift == ip >> shift
+}
+
+// The address of the client behind any trusted proxies. X-Forwarded-For is
+// only believed when the connection comes from a trusted proxy, and then read
+// from the right, stopping at the first hop that isn't trusted itself.
+pub fn client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpRange]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in forwarded_for.unwrap_or("").rsplit(',') {
+        // A malformed entry can't be attributed, so the last good hop wins
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = hop;
+        if !is_trusted(hop) {
+            break;
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges_parse_and_match() {
+        let range = IpRange::parse("10.0.0.0/8").unwrap();
+        assert!(range.contains(ip("10.1.2.3")));
+        assert!(!range.contains(ip("11.0.0.1")));
+        assert!(!range.contains(ip("::1")));
+        assert!(IpRange::parse("127.0.0.1").unwrap().contains(ip("127.0.0.1")));
+        assert!(IpRange::parse("fd00::/8").unwrap().contains(ip("fd12::1")));
+        assert!(IpRange::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+        assert_eq!(IpRange::parse("10.0.0.0/33"), None);
+        assert_eq!(IpRange::parse("nope"), None);
+    }
+
+    #[test]
+    fn forwarded_for_from_an_untrusted_peer_is_ignored() {
+        let trusted = [IpRange::parse("10.0.0.0/8").unwrap()];
+        let client = client_ip(ip("203.0.113.9"), Some("198.51.100.1"), &trusted);
+        assert_eq!(client, ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn forwarded_for_from_a_trusted_peer_is_believed() {
+        let trusted = [IpRange::parse("10.0.0.0/8").unwrap()];
+        let client = client_ip(ip("10.0.0.2"), Some("198.51.100.1"), &trusted);
+        assert_eq!(client, ip("198.51.100.1"));
+        assert_eq!(client_ip(ip("10.0.0.2"), None, &trusted), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn forwarded_for_stops_at_the_first_untrusted_hop() {
+        let trusted = [IpRange::parse("10.0.0.0/8").unwrap()];
+        // The leftmost entry was supplied by the client and can't be believed
+        let chain = "1.1.1.1, 198.51.100.1, 10.0.0.5";
+        assert_eq!(client_ip(ip("10.0.0.2"), Some(chain), &trusted), ip("198.51.100.1"));
+        assert_eq!(client_ip(ip("10.0.0.2"), Some("junk, 10.0.0.5"), &trusted), ip("10.0.0.5"));
+    }
+}
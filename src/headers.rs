@@ -0,0 +1,255 @@
+#![allow(dead_code)]
+use std::fmt;
+
+// A parsed Content-Type such as "text/html; charset=utf-8". Type, subtype and
+// parameter names are lowercased; parameter values keep their case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    pub type_: String,
+    pub subtype: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn new(type_: &str, subtype: &str) -> Self {
+        MediaType {
+            type_: type_.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    pub fn parse(value: &str) -> Option<MediaType> {
+        let mut parts = value.split(';');
+        let (type_, subtype) = parts.next()?.trim().split_once('/')?;
+        if !is_token(type_) || !is_token(subtype) {
+            return None;
+        }
+
+        let mut media_type = MediaType::new(type_, subtype);
+        for param in parts {
+            let param = param.trim();
+            if param.is_empty() {
+                continue;
+            }
+            let (name, value) = param.split_once('=')?;
+            let name = name.trim();
+            if !is_token(name) {
+                return None;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            media_type = media_type.with_param(name, value);
+        }
+        Some(media_type)
+    }
+
+    // "type/subtype" without parameters
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.type_, self.subtype)
+    }
+
+    // Whether this type falls under `pattern`, which may be a wildcard range
+    // such as "text/*" or "*/*". Parameters are ignored.
+    pub fn matches(&self, pattern: &MediaType) -> bool {
+        (pattern.type_ == "*" || pattern.type_ == self.type_)
+            && (pattern.subtype == "*" || pattern.subtype == self.subtype)
+    }
+
+    // How narrow a range is: 2 for "text/html", 1 for "text/*", 0 for "*/*"
+    pub fn specificity(&self) -> u8 {
+        (self.type_ != "*") as u8 + (self.subtype != "*") as u8
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.type_, self.subtype)?;
+        for (name, value) in &self.params {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"{}\"", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Credentials from an Authorization header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    Basic { username: String, password: String },
+    Bearer(String),
+    // Any other scheme, with its credentials left as sent
+    Other { scheme: String, credentials: String },
+}
+
+impl Auth {
+    pub fn parse(value: &str) -> Option<Auth> {
+        let (scheme, credentials) = value.trim().split_once(' ')?;
+        let credentials = credentials.trim();
+        if credentials.is_empty() {
+            return None;
+        }
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = String::from_utf8(decode_base64(credentials)?).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+            Some(Auth::Basic {
+                username: username.to_string(),
+                password: password.to_string(),
+            })
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            Some(Auth::Bearer(credentials.to_string()))
+        } else {
+            Some(Auth::Other {
+                scheme: scheme.to_string(),
+                credentials: credentials.to_string(),
+            })
+        }
+    }
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// Standard alphabet with padding, as Basic credentials use
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let input = input.as_bytes();
+    if !input.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    for (index, group) in input.chunks(4).enumerate() {
+        let is_last = index == input.len() / 4 - 1;
+        let padding = group.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return None;
+        }
+
+        let mut bits: u32 = 0;
+        for &byte in &group[..4 - padding] {
+            let value = match byte {
+                b'A'..=b'Z' => byte - b'A',
+                b'a'..=b'z' => byte - b'a' + 26,
+                b'0'..=b'9' => byte - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                _ => return None,
+            };
+            bits = (bits << 6) | value as u32;
+        }
+        bits <<= 6 * padding;
+
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        output.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+
+    #[test]
+    fn media_type_parses_type_and_params() {
+        let media_type = MediaType::parse("Text/HTML; Charset=\"UTF-8\"; q=0.5").unwrap();
+        assert_eq!(media_type.essence(), "text/html");
+        assert_eq!(media_type.param("charset"), Some("UTF-8"));
+        assert_eq!(media_type.param("q"), Some("0.5"));
+        assert_eq!(media_type.to_string(), "text/html; charset=UTF-8; q=0.5");
+    }
+
+    #[test]
+    fn media_type_rejects_malformed_values() {
+        assert_eq!(MediaType::parse("texthtml"), None);
+        assert_eq!(MediaType::parse("text/"), None);
+        assert_eq!(MediaType::parse("text/html; charset"), None);
+        assert_eq!(MediaType::parse("te xt/html"), None);
+    }
+
+    #[test]
+    fn auth_parses_basic_bearer_and_other_schemes() {
+        assert_eq!(
+            Auth::parse("Basic YWxhZGRpbjpvcGVuc2VzYW1l"),
+            Some(Auth::Basic {
+                username: "aladdin".to_string(),
+                password: "opensesame".to_string(),
+            })
+        );
+        assert_eq!(Auth::parse("bearer abc.def"), Some(Auth::Bearer("abc.def".to_string())));
+        assert_eq!(
+            Auth::parse("Digest realm=x"),
+            Some(Auth::Other {
+                scheme: "Digest".to_string(),
+                credentials: "realm=x".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn auth_rejects_malformed_values() {
+        assert_eq!(Auth::parse("Basic"), None);
+        assert_eq!(Auth::parse("Basic !!!!"), None);
+        assert_eq!(Auth::parse("Basic YWxhZGRpbg=="), None);
+        assert_eq!(Auth::parse("Bearer  "), None);
+    }
+
+    #[test]
+    fn request_exposes_typed_headers() {
+        let request = RequestBuilder::post("/")
+            .header("Content-Type", "application/json")
+            .header("Authorization", "Bearer token")
+            .body("{}")
+            .build();
+        assert_eq!(request.content_type().map(|t| t.essence()), Some("application/json".to_string()));
+        assert_eq!(request.content_length(), Some(2));
+        assert_eq!(request.authorization(), Some(Auth::Bearer("token".to_string())));
+
+        let request = RequestBuilder::get("/").header("Content-Type", "nonsense").build();
+        assert_eq!(request.content_type(), None);
+        assert_eq!(request.content_length(), None);
+        assert_eq!(request.authorization(), None);
+    }
+
+    #[test]
+    fn media_type_matches_wildcard_ranges() {
+        let html = MediaType::parse("text/html; charset=utf-8").unwrap();
+        assert!(html.matches(&MediaType::parse("text/html").unwrap()));
+        assert!(html.matches(&MediaType::parse("text/*").unwrap()));
+        assert!(html.matches(&MediaType::parse("*/*").unwrap()));
+        assert!(html.matches(&MediaType::parse("TEXT/HTML").unwrap()));
+        assert!(!html.matches(&MediaType::parse("application/*").unwrap()));
+        assert!(!html.matches(&MediaType::parse("text/plain").unwrap()));
+    }
+
+    #[test]
+    fn media_type_specificity_ranks_ranges() {
+        let specificity = |value| MediaType::parse(value).unwrap().specificity();
+        assert_eq!(specificity("text/html"), 2);
+        assert_eq!(specificity("text/*"), 1);
+        assert_eq!(specificity("*/*"), 0);
+    }
+}
@@ -1,79 +1,1184 @@
 #![allow(dead_code)]
+use crate::headers::{Auth, MediaType};
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::io::{self, BufRead, Read};
+use std::str::{FromStr, Utf8Error};
+use std::time::{Duration, Instant};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum HttpMethod {
     GET,
+    HEAD,
     POST,
     PUT,
+    PATCH,
     DELETE,
+    OPTIONS,
+    TRACE,
+    CONNECT,
 }
 
 impl HttpMethod {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(method: &str) -> Option<HttpMethod> {
         match method {
             "GET" => Some(HttpMethod::GET),
+            "HEAD" => Some(HttpMethod::HEAD),
             "POST" => Some(HttpMethod::POST),
             "PUT" => Some(HttpMethod::PUT),
+            "PATCH" => Some(HttpMethod::PATCH),
             "DELETE" => Some(HttpMethod::DELETE),
+            "OPTIONS" => Some(HttpMethod::OPTIONS),
+            "TRACE" => Some(HttpMethod::TRACE),
+            "CONNECT" => Some(HttpMethod::CONNECT),
             _ => None,
         }
     }
+
+    fn allows_unframed_body(&self) -> bool {
+        !matches!(
+            self,
+            HttpMethod::GET
+                | HttpMethod::HEAD
+                | HttpMethod::DELETE
+                | HttpMethod::OPTIONS
+                | HttpMethod::TRACE
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::CONNECT => "CONNECT",
+        }
+    }
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+// Accepts only plain decimal digits, so values like "-5", "+5", "abc" or
+// anything overflowing usize are rejected instead of being read as zero
+pub fn parse_content_length(value: &str) -> Option<usize> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+pub type Fields = Vec<(String, String)>;
+
+// Decodes a chunked body up to and including the terminating zero-size chunk,
+// returning it with any trailer fields (names lowercased). Chunk extensions
+// are skipped.
+pub fn read_chunked<R: BufRead>(reader: &mut R) -> Option<(Vec<u8>, Fields)> {
+    read_chunked_limited(reader, usize::MAX, usize::MAX).ok()
+}
+
+// Why a request head couldn't be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadError {
+    Malformed,
+    // The request line and headers didn't end within the limit
+    TooLarge,
+}
+
+// Why a body couldn't be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyError {
+    // Bad framing, or the connection ended early
+    Malformed,
+    // Longer than the limit it was read with
+    TooLarge,
+    // A Transfer-Encoding other than chunked, such as "gzip, chunked"
+    Unsupported,
+    // Chunked trailers past the limit they were read with
+    TrailersTooLarge,
+}
+
+// Why param_as couldn't produce a value. A handler returning it as an error
+// gets a 400 unless an error_handler maps it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    // No such parameter, such as an optional segment that was left off
+    Missing(String),
+    // The segment didn't parse as the type asked for
+    Invalid { name: String, value: String },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "missing path parameter {}", name),
+            ParamError::Invalid { name, value } => {
+                write!(f, "invalid path parameter {}: {:?}", name, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+// Longest chunk-size line accepted, extensions included. The size itself is
+// a few hex digits; this only leaves room for reasonable extensions.
+pub const MAX_CHUNK_LINE_BYTES: usize = 1024;
+
+// Reads one line, line ending included, of at most `max_bytes`, so a line
+// that never ends can't grow without bound. TooLarge if it runs past
+// `max_bytes`, Malformed if the input ends first or isn't UTF-8.
+fn read_line_limited<R: BufRead>(reader: &mut R, max_bytes: usize) -> Result<String, HeadError> {
+    let mut limited = reader.take(max_bytes as u64);
+    let mut line = Vec::new();
+    let read = limited
+        .read_until(b'\n', &mut line)
+        .map_err(|_| HeadError::Malformed)?;
+    if read == 0 || !line.ends_with(b"\n") {
+        return Err(if limited.limit() == 0 {
+            HeadError::TooLarge
+        } else {
+            HeadError::Malformed
+        });
+    }
+    String::from_utf8(line).map_err(|_| HeadError::Malformed)
+}
+
+// Like read_chunked, but gives up as soon as the body outgrows `limit` or the
+// trailers, line endings included, outgrow `max_trailer_bytes`
+fn read_chunked_limited<R: BufRead>(
+    reader: &mut R,
+    limit: usize,
+    max_trailer_bytes: usize,
+) -> Result<(Vec<u8>, Fields), BodyError> {
+    let malformed = BodyError::Malformed;
+    let mut body = Vec::new();
+    loop {
+        let line = read_line_limited(reader, MAX_CHUNK_LINE_BYTES).map_err(|_| malformed)?;
+        let size = trim_line_ending(&line).split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| malformed)?;
+
+        if size == 0 {
+            let mut trailers = Vec::new();
+            let mut budget = max_trailer_bytes;
+            loop {
+                let line = read_line_limited(reader, budget).map_err(|e| match e {
+                    HeadError::TooLarge => BodyError::TrailersTooLarge,
+                    HeadError::Malformed => malformed,
+                })?;
+                budget -= line.len();
+                let line = trim_line_ending(&line);
+                if line.is_empty() {
+                    return Ok((body, trailers));
+                }
+                let (key, value) = line.split_once(':').ok_or(malformed)?;
+                trailers.push((key.trim().to_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        if size > limit - body.len() {
+            return Err(BodyError::TooLarge);
+        }
+        body.extend(read_bytes(reader, size as u64).map_err(|_| malformed)?);
+        // Just the CRLF ending the chunk's data
+        let crlf = read_line_limited(reader, 2).map_err(|_| malformed)?;
+        if !trim_line_ending(&crlf).is_empty() {
+            return Err(malformed);
+        }
+    }
+}
+
+// A request body read straight from the connection, ending where its
+// Content-Length or final chunk says it does. Chunked bodies are decoded as
+// they're read; their trailers are discarded, but still count against
+// max_trailer_bytes.
+pub struct BodyReader<'a, R> {
+    reader: &'a mut R,
+    chunked: bool,
+    max_trailer_bytes: usize,
+    // Bytes left in the body, or in the current chunk when chunked
+    remaining: u64,
+    started: bool,
+    done: bool,
+}
+
+impl<'a, R: BufRead> BodyReader<'a, R> {
+    // Caps the trailers of a chunked body, DEFAULT_MAX_HEADER_BYTES unless set
+    pub fn max_trailer_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_trailer_bytes = max_bytes;
+        self
+    }
+
+    // One line of at most `max_bytes`, as read_chunked reads them
+    fn line(&mut self, max_bytes: usize, what: &str) -> io::Result<String> {
+        read_line_limited(self.reader, max_bytes).map_err(|e| {
+            let message = match e {
+                HeadError::TooLarge => format!("{what} too large"),
+                HeadError::Malformed => format!("malformed {what}"),
+            };
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed chunked body");
+        if self.started {
+            // The CRLF closing the previous chunk's data
+            if !trim_line_ending(&self.line(2, "chunk ending")?).is_empty() {
+                return Err(invalid());
+            }
+        }
+        self.started = true;
+
+        let line = self.line(MAX_CHUNK_LINE_BYTES, "chunk size line")?;
+        let size = trim_line_ending(&line).split(';').next().unwrap_or("").trim();
+        self.remaining = u64::from_str_radix(size, 16).map_err(|_| invalid())?;
+
+        if self.remaining == 0 {
+            let mut budget = self.max_trailer_bytes;
+            loop {
+                let line = self.line(budget, "trailers")?;
+                budget -= line.len();
+                if trim_line_ending(&line).is_empty() {
+                    break;
+                }
+            }
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunked && self.remaining == 0 && !self.done {
+            self.next_chunk()?;
+        }
+        if self.done || self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let limit = buf.len().min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let read = self.reader.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read as u64;
+        if !self.chunked && self.remaining == 0 {
+            self.done = true;
+        }
+        Ok(read)
+    }
+}
+
+// Reads exactly `length` bytes. The buffer grows with what actually arrives,
+// so a huge declared length can't allocate memory up front.
+pub(crate) fn read_bytes<R: Read>(reader: &mut R, length: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(length).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+// Fields that control framing, routing or authentication can't be sent as trailers
+const FORBIDDEN_TRAILERS: [&str; 9] = [
+    "authorization",
+    "cache-control",
+    "content-encoding",
+    "content-length",
+    "content-range",
+    "content-type",
+    "host",
+    "te",
+    "transfer-encoding",
+];
+
+// Headers a request may carry only once
+const SINGLE_HEADERS: [&str; 3] = ["content-length", "host", "transfer-encoding"];
+
+// Upper bound on the request line plus headers used by read_from
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 8192;
+
+// Most %XX escapes the path, or the query, may contain. Enough for around 80
+// percent-encoded non-Latin characters; far more only serves to hide what the
+// target really says.
+pub const MAX_TARGET_ESCAPES: usize = 256;
+
+// The inverse of percent_decode for a path: escapes every byte a path
+// segment can't hold as is, leaving '/' as the separator
+pub(crate) fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~!$&'()*+,;=:@".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Decodes %XX escapes; None if one is malformed or the result isn't UTF-8.
+// '+' is left alone, as it only means a space in form-encoded queries.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// Whether every '%' starts a two-digit hex escape. Unlike percent_decode this
+// doesn't require the decoded bytes to be UTF-8.
+fn valid_escapes(input: &str) -> bool {
+    let bytes = input.as_bytes();
+    bytes.iter().enumerate().all(|(i, &b)| {
+        b != b'%'
+            || bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    })
+}
+
+fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Values keyed by their type, for middleware to hand data such as an
+// authenticated user to handlers
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    // Returns the value of the same type that was there before, if any
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Extensions({} values)", self.map.len())
+    }
 }
 
 #[derive(Debug)]
 pub struct HttpRequest {
     pub method: HttpMethod,
+    // The request target exactly as sent, e.g. "/search%20results?q=a%2Bb"
+    pub raw_target: String,
+    // The target's path, percent-decoded ("/search results"), used for routing
     pub path: String,
+    // Everything after the '?', still percent-encoded since decoding can only
+    // happen per parameter ("q=a%2Bb")
+    pub query: Option<String>,
+    pub version: String,
     pub headers: HashMap<String, String>,
-    pub body: String,
+    pub body: Vec<u8>,
+    // Fields sent after a chunked body, limited to those the Trailer header declared
+    pub trailers: HashMap<String, String>,
+    // Address of the client, filled in by the server
+    pub peer_addr: Option<SocketAddr>,
+    // The originating client: peer_addr, or an X-Forwarded-For entry when the
+    // peer is one of the server's trusted proxies
+    pub client_ip: Option<IpAddr>,
+    pub extensions: Extensions,
+    // Values of the `:name` segments of the matched route template
+    pub params: HashMap<String, String>,
+    // When the server's request budget runs out, if it has one
+    pub(crate) deadline: Option<Instant>,
+    // Sent with both Content-Length and Transfer-Encoding. The body is read
+    // as chunked, but something in front may have framed it by the length,
+    // so the server closes the connection after answering.
+    pub(crate) framing_conflict: bool,
 }
 
 impl HttpRequest {
+    // Lines may end in CRLF or, leniently, a bare LF
     pub fn parse(request: &str) -> Option<HttpRequest> {
-        let lines: Vec<&str> = request.split("\r\n").collect();
-        if lines.is_empty() {
-            return None;
-        }
+        let mut lines = request.split_inclusive('\n');
+        let mut consumed = 0;
+        let mut next_line = || {
+            let line = lines.next()?;
+            consumed += line.len();
+            Some(trim_line_ending(line))
+        };
 
         // Parse request line: "GET /path HTTP/1.1"
-        let request_line_parts: Vec<&str> = lines[0].split_whitespace().collect();
+        let request_line_parts: Vec<&str> = next_line()?.split_whitespace().collect();
         if request_line_parts.len() != 3 {
             return None;
         }
 
         let method = HttpMethod::from_str(request_line_parts[0])?;
-        let path = request_line_parts[1].to_string();
+        // A NUL or other control character in the target is never legitimate and
+        // could reach the filesystem through static file serving
+        if request_line_parts[1].chars().any(|c| c.is_control()) {
+            return None;
+        }
+        let raw_target = request_line_parts[1].to_string();
+        let (path, query) = match raw_target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (raw_target.as_str(), None),
+        };
+        let escapes = |part: &str| part.bytes().filter(|&b| b == b'%').count();
+        if escapes(path) > MAX_TARGET_ESCAPES
+            || query.as_deref().is_some_and(|query| escapes(query) > MAX_TARGET_ESCAPES)
+        {
+            return None;
+        }
+        // The query stays encoded, but a broken escape such as %zz or a
+        // trailing %2 is refused now rather than reaching a handler
+        if query.as_deref().is_some_and(|query| !valid_escapes(query)) {
+            return None;
+        }
+        // An encoded control character is refused just like a raw one
+        let path = percent_decode(path)?;
+        if path.chars().any(|c| c.is_control()) {
+            return None;
+        }
+        let version = request_line_parts[2].to_string();
 
         // Parse headers
         let mut headers = HashMap::new();
-        let mut body_start = 0;
 
-        for (i, line) in lines.iter().enumerate().skip(1) {
+        while let Some(line) = next_line() {
             if line.is_empty() {
-                body_start = i + 1;
                 break;
             }
+
+            // Obsolete line folding (a continuation line starting with
+            // whitespace) must be rejected rather than guessed at
+            if line.starts_with(' ') || line.starts_with('\t') {
+                return None;
+            }
             
             if let Some(colon_pos) = line.find(':') {
-                let key = line[..colon_pos].trim().to_lowercase();
+                // Whitespace before the colon lets two parsers disagree on
+                // the name ("Content-Length :"), so it's refused, as is a
+                // line with no name at all
+                let key = &line[..colon_pos];
+                if key.is_empty() || key.ends_with([' ', '\t']) {
+                    return None;
+                }
+                let key = key.to_lowercase();
                 let value = line[colon_pos + 1..].trim().to_string();
+                // A second copy of a framing or routing header is refused too,
+                // rather than letting the last one silently win
+                if SINGLE_HEADERS.contains(&key.as_str()) && headers.contains_key(&key) {
+                    return None;
+                }
                 headers.insert(key, value);
             }
         }
 
-        // Parse body (for POST requests)
-        let body = if body_start < lines.len() {
-            lines[body_start..].join("\r\n")
+        // Parse body (for POST requests): everything after the blank line. GET,
+        // HEAD and DELETE only get one when its framing is declared, so trailing
+        // bytes such as a pipelined request don't end up in the body.
+        let framing_conflict =
+            headers.contains_key("content-length") && headers.contains_key("transfer-encoding");
+        let framed =
+            headers.contains_key("content-length") || headers.contains_key("transfer-encoding");
+        let body = if framed || method.allows_unframed_body() {
+            request.as_bytes()[consumed..].to_vec()
         } else {
-            String::new()
+            Vec::new()
         };
 
         Some(HttpRequest {
             method,
+            raw_target,
             path,
+            query,
+            version,
             headers,
             body,
+            trailers: HashMap::new(),
+            peer_addr: None,
+            client_ip: None,
+            extensions: Extensions::default(),
+            params: HashMap::new(),
+            deadline: None,
+            framing_conflict,
         })
     }
+
+    // Parses a complete request, body included, from arbitrary bytes. Never
+    // panics, whatever the input, so it doubles as the fuzzing entry point.
+    pub fn parse_bytes(bytes: &[u8]) -> Option<HttpRequest> {
+        HttpRequest::read_from(&mut io::Cursor::new(bytes))
+    }
+
+    // Reads the request line and headers line by line, then exactly
+    // Content-Length bytes of body, so requests aren't capped by a fixed buffer
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Option<HttpRequest> {
+        let mut request = HttpRequest::read_head(reader, DEFAULT_MAX_HEADER_BYTES)?;
+        request.read_body(reader)?;
+        Some(request)
+    }
+
+    // Reads and parses everything up to the blank line ending the headers,
+    // leaving the body unread. Fails if the blank line doesn't arrive within
+    // `max_bytes`, so a client can't grow the head without bound.
+    pub fn read_head<R: BufRead>(reader: &mut R, max_bytes: usize) -> Option<HttpRequest> {
+        HttpRequest::read_head_limited(reader, max_bytes).ok()
+    }
+
+    // Like read_head, telling a head that ran past `max_bytes` (TooLarge) apart
+    // from one that's malformed or cut short. Lines are read as bytes through a
+    // bounded reader, so even a line that never ends stops at the limit.
+    pub fn read_head_limited<R: BufRead>(
+        reader: &mut R,
+        max_bytes: usize,
+    ) -> Result<HttpRequest, HeadError> {
+        let mut reader = reader.take(max_bytes as u64);
+        let mut head = Vec::new();
+        loop {
+            let start = head.len();
+            let read = reader
+                .read_until(b'\n', &mut head)
+                .map_err(|_| HeadError::Malformed)?;
+            if read == 0 || !head.ends_with(b"\n") {
+                // Either the limit cut the line off or the connection ended
+                return Err(if reader.limit() == 0 {
+                    HeadError::TooLarge
+                } else {
+                    HeadError::Malformed
+                });
+            }
+            if matches!(&head[start..], b"\r\n" | b"\n") {
+                break;
+            }
+        }
+
+        let head = std::str::from_utf8(&head).map_err(|_| HeadError::Malformed)?;
+        HttpRequest::parse(head).ok_or(HeadError::Malformed)
+    }
+
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R) -> Option<()> {
+        self.read_body_limited(reader, usize::MAX, usize::MAX).ok()
+    }
+
+    // Like read_body, but fails with TooLarge for a body over `limit` bytes,
+    // without reading it when the Content-Length already says so, and with
+    // TrailersTooLarge for chunked trailers over `max_trailer_bytes`. Chunked
+    // is the only transfer coding understood; any other is Unsupported.
+    pub fn read_body_limited<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        limit: usize,
+        max_trailer_bytes: usize,
+    ) -> Result<(), BodyError> {
+        if self.is_chunked().ok_or(BodyError::Unsupported)? {
+            let (body, trailers) = read_chunked_limited(reader, limit, max_trailer_bytes)?;
+            self.body = body;
+            self.add_trailers(trailers);
+            return Ok(());
+        }
+
+        let content_length = match self.headers.get("content-length") {
+            Some(value) => parse_content_length(value).ok_or(BodyError::Malformed)?,
+            None => 0,
+        };
+        if content_length > limit {
+            return Err(BodyError::TooLarge);
+        }
+        self.body = read_bytes(reader, content_length as u64).map_err(|_| BodyError::Malformed)?;
+        Ok(())
+    }
+
+    // The body as a reader over `reader`, for handlers that stream it instead
+    // of having read_body buffer it. None if the Content-Length is malformed
+    // or the Transfer-Encoding isn't chunked.
+    pub fn body_reader<'a, R: BufRead>(&self, reader: &'a mut R) -> Option<BodyReader<'a, R>> {
+        let chunked = self.is_chunked()?;
+        let remaining = match self.headers.get("content-length") {
+            Some(value) if !chunked => parse_content_length(value)? as u64,
+            _ => 0,
+        };
+        Some(BodyReader {
+            reader,
+            chunked,
+            max_trailer_bytes: DEFAULT_MAX_HEADER_BYTES,
+            remaining,
+            started: false,
+            done: !chunked && remaining == 0,
+        })
+    }
+
+    // Whether the body is chunked; None for a Transfer-Encoding other than
+    // exactly chunked, including a list such as "gzip, chunked"
+    pub fn is_chunked(&self) -> Option<bool> {
+        match self.headers.get("transfer-encoding") {
+            Some(value) if value.eq_ignore_ascii_case("chunked") => Some(true),
+            Some(_) => None,
+            None => Some(false),
+        }
+    }
+
+    fn add_trailers(&mut self, trailers: Fields) {
+        let declared: Vec<String> = self
+            .headers
+            .get("trailer")
+            .map(|value| value.split(',').map(|name| name.trim().to_lowercase()).collect())
+            .unwrap_or_default();
+
+        for (key, value) in trailers {
+            if declared.contains(&key) && !FORBIDDEN_TRAILERS.contains(&key.as_str()) {
+                self.trailers.insert(key, value);
+            }
+        }
+    }
+
+    // The comma-separated options of the Connection header, lowercased, so
+    // "keep-alive, Upgrade" gives ["keep-alive", "upgrade"]
+    pub fn connection_tokens(&self) -> Vec<String> {
+        self.headers
+            .get("connection")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|token| token.trim().to_ascii_lowercase())
+                    .filter(|token| !token.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Such as "close", "keep-alive" or "upgrade", compared case-insensitively
+    pub fn has_connection_token(&self, token: &str) -> bool {
+        self.connection_tokens()
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(token))
+    }
+
+    // None when the header is missing or malformed
+    pub fn content_length(&self) -> Option<u64> {
+        let value = self.headers.get("content-length")?;
+        parse_content_length(value).map(|length| length as u64)
+    }
+
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(self.headers.get("content-type")?)
+    }
+
+    // Which of the media types a handler can produce ("application/json", ...)
+    // the Accept header ranks highest, each judged by the most specific range
+    // matching it. None means none is acceptable and the answer should be a 406.
+    // Without an Accept header anything goes, so the first offer is chosen.
+    pub fn preferred<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let Some(accept) = self.headers.get("accept") else {
+            return offered.first().copied();
+        };
+        let ranges: Vec<MediaType> = accept.split(',').filter_map(MediaType::parse).collect();
+
+        let mut best: Option<(u32, &'a str)> = None;
+        for &offer in offered {
+            let Some(offer_type) = MediaType::parse(offer) else {
+                continue;
+            };
+            let range = ranges
+                .iter()
+                .filter(|range| offer_type.matches(range))
+                .max_by_key(|range| range.specificity());
+
+            // q-values have at most three decimals, so compare them as thousandths
+            let quality = range.map_or(0, |range| {
+                let q: f32 = range.param("q").and_then(|q| q.parse().ok()).unwrap_or(1.0);
+                (q.clamp(0.0, 1.0) * 1000.0).round() as u32
+            });
+            if quality > 0 && best.is_none_or(|(best, _)| quality > best) {
+                best = Some((quality, offer));
+            }
+        }
+        best.map(|(_, offer)| offer)
+    }
+
+    // The segment of the path matched by `:name` in the route, so "/api/users/:id"
+    // gives param("id") == Some("7") for "/api/users/7"
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    // param(name) parsed as T, so a handler can write
+    // `let id: u64 = req.param_as("id")?;` and have "/api/users/abc" get a 400
+    pub fn param_as<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .param(name)
+            .ok_or_else(|| ParamError::Missing(name.to_string()))?;
+        value.parse().map_err(|_| ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    // The point by which the whole response should be done, from
+    // ServerConfig::request_budget. Long handlers can check it and stop early.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // How much of the budget is left, zero once the deadline has passed
+    pub fn time_left(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn authorization(&self) -> Option<Auth> {
+        Auth::parse(self.headers.get("authorization")?)
+    }
+
+    // The body is kept as raw bytes; this fails instead of replacing invalid UTF-8
+    pub fn body_string(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+
+    // An application/x-www-form-urlencoded body with every value of each key,
+    // in order, so a multi-select's `tags[]=a&tags[]=b` gives "tags" both
+    // values. A trailing `[]` is dropped from keys. None if the body isn't a
+    // form or doesn't decode.
+    pub fn form_multi(&self) -> Option<HashMap<String, Vec<String>>> {
+        let is_form = self
+            .content_type()
+            .is_some_and(|media_type| media_type.essence() == "application/x-www-form-urlencoded");
+        if !is_form {
+            return None;
+        }
+
+        let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+        for pair in self.body_string().ok()?.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = form_decode(key)?;
+            let key = key.strip_suffix("[]").unwrap_or(&key);
+            fields.entry(key.to_string()).or_default().push(form_decode(value)?);
+        }
+        Some(fields)
+    }
+}
+
+// A form-encoded name or value, where '+' stands for a space
+fn form_decode(input: &str) -> Option<String> {
+    percent_decode(&input.replace('+', " "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+
+    #[test]
+    fn content_length_accepts_plain_digits() {
+        assert_eq!(parse_content_length("42"), Some(42));
+        assert_eq!(parse_content_length(" 7 "), Some(7));
+        assert_eq!(parse_content_length("0"), Some(0));
+    }
+
+    #[test]
+    fn content_length_rejects_empty_negative_and_overflowing_values() {
+        assert_eq!(parse_content_length(""), None);
+        assert_eq!(parse_content_length("  "), None);
+        assert_eq!(parse_content_length("-5"), None);
+        assert_eq!(parse_content_length("+5"), None);
+        assert_eq!(parse_content_length("abc"), None);
+        assert_eq!(parse_content_length("99999999999999999999999999"), None);
+    }
+
+    #[test]
+    fn duplicate_content_length_is_rejected() {
+        let request = "POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 5\r\n\r\nabc";
+        assert!(HttpRequest::parse(request).is_none());
+        let request = "POST / HTTP/1.1\r\nContent-Length: 3\r\ncontent-length: 3\r\n\r\nabc";
+        assert!(HttpRequest::parse(request).is_none());
+    }
+
+    #[test]
+    fn whitespace_before_the_colon_is_rejected() {
+        let request = "POST / HTTP/1.1\r\nContent-Length : 3\r\n\r\nabc";
+        assert!(HttpRequest::parse(request).is_none());
+    }
+
+    #[test]
+    fn folded_header_lines_are_rejected() {
+        let request = "GET / HTTP/1.1\r\nX-Long: first\r\n  second\r\n\r\n";
+        assert!(HttpRequest::parse(request).is_none());
+        let request = "GET / HTTP/1.1\r\nX-Long: first\r\n\tsecond\r\n\r\n";
+        assert!(HttpRequest::parse(request).is_none());
+    }
+
+    #[test]
+    fn methods_round_trip_through_their_names() {
+        let methods = [
+            HttpMethod::GET,
+            HttpMethod::HEAD,
+            HttpMethod::POST,
+            HttpMethod::PUT,
+            HttpMethod::PATCH,
+            HttpMethod::DELETE,
+            HttpMethod::OPTIONS,
+            HttpMethod::TRACE,
+            HttpMethod::CONNECT,
+        ];
+        for method in methods {
+            assert_eq!(HttpMethod::from_str(method.as_str()), Some(method.clone()));
+            assert_eq!(method.to_string(), method.as_str());
+        }
+        assert_eq!(HttpMethod::from_str("get"), None);
+        assert_eq!(format!("{:<6}|", HttpMethod::GET), "GET   |");
+    }
+
+    #[test]
+    fn bare_lf_line_endings_parse() {
+        let request = HttpRequest::parse("POST /api/users HTTP/1.1\nHost: x\nContent-Length: 2\n\nhi")
+            .unwrap();
+        assert_eq!(request.method, HttpMethod::POST);
+        assert_eq!(request.path, "/api/users");
+        assert_eq!(request.headers.get("host").map(String::as_str), Some("x"));
+        assert_eq!(request.body, b"hi");
+
+        let request = HttpRequest::parse_bytes(b"GET / HTTP/1.1\nHost: x\n\n").unwrap();
+        assert_eq!(request.path, "/");
+    }
+
+    #[test]
+    fn head_cut_short_is_malformed() {
+        let mut reader = io::Cursor::new(b"GET / HTTP/1.1\r\nHost: x\r\n".to_vec());
+        assert_eq!(
+            HttpRequest::read_head_limited(&mut reader, 1024).err(),
+            Some(HeadError::Malformed)
+        );
+        let mut reader = io::Cursor::new(b"GET / HTTP/1.1\r\nHost: x".to_vec());
+        assert_eq!(
+            HttpRequest::read_head_limited(&mut reader, 1024).err(),
+            Some(HeadError::Malformed)
+        );
+    }
+
+    #[test]
+    fn connection_tokens_are_split_and_lowercased() {
+        let request = HttpRequest::parse("GET / HTTP/1.1\r\nConnection: Keep-Alive, Upgrade,, TE\r\n\r\n")
+            .unwrap();
+        assert_eq!(request.connection_tokens(), ["keep-alive", "upgrade", "te"]);
+        assert!(request.has_connection_token("upgrade"));
+        assert!(request.has_connection_token("KEEP-ALIVE"));
+        assert!(!request.has_connection_token("close"));
+
+        let request = HttpRequest::parse("GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(request.has_connection_token("close"));
+        let request = HttpRequest::parse("GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(request.connection_tokens().is_empty());
+    }
+
+    #[test]
+    fn declared_trailers_follow_a_chunked_body() {
+        let raw = "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                   Trailer: X-Checksum, Content-Length\r\n\r\n\
+                   5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\
+                   X-Checksum: abc123\r\nContent-Length: 99\r\nX-Undeclared: 1\r\n\r\n";
+        let request = HttpRequest::parse_bytes(raw.as_bytes()).unwrap();
+        assert_eq!(request.body, b"hello world");
+        assert_eq!(request.trailers.get("x-checksum").map(String::as_str), Some("abc123"));
+        // Framing fields and undeclared ones are dropped
+        assert!(!request.trailers.contains_key("content-length"));
+        assert!(!request.trailers.contains_key("x-undeclared"));
+    }
+
+    #[test]
+    fn transfer_codings_other_than_chunked_are_unsupported() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n";
+        let mut reader = io::Cursor::new(raw.as_bytes());
+        let mut request = HttpRequest::read_head(&mut reader, 1024).unwrap();
+        assert_eq!(request.is_chunked(), None);
+        assert_eq!(
+            request.read_body_limited(&mut reader, 1024, DEFAULT_MAX_HEADER_BYTES),
+            Err(BodyError::Unsupported)
+        );
+        assert!(request.body_reader(&mut reader).is_none());
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding_is_a_framing_conflict() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   1\r\na\r\n0\r\n\r\n";
+        let request = HttpRequest::parse_bytes(raw.as_bytes()).unwrap();
+        assert!(request.framing_conflict);
+        // The chunked framing wins
+        assert_eq!(request.body, b"a");
+    }
+
+    #[test]
+    fn extensions_are_keyed_by_type() {
+        let mut extensions = Extensions::default();
+        assert_eq!(extensions.insert(5u32), None);
+        assert_eq!(extensions.insert("name"), None);
+        assert_eq!(extensions.insert(7u32), Some(5));
+        *extensions.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(extensions.get::<u32>(), Some(&8));
+        assert_eq!(extensions.remove::<&str>(), Some("name"));
+        assert_eq!(extensions.get::<&str>(), None);
+    }
+
+    #[test]
+    fn unframed_trailing_bytes_are_not_a_get_body() {
+        let raw = "GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+        assert!(HttpRequest::parse(raw).unwrap().body.is_empty());
+        let raw = "DELETE /a HTTP/1.1\r\n\r\nleftover";
+        assert!(HttpRequest::parse(raw).unwrap().body.is_empty());
+        let raw = "POST /a HTTP/1.1\r\n\r\nleftover";
+        assert_eq!(HttpRequest::parse(raw).unwrap().body, b"leftover");
+    }
+
+    #[test]
+    fn framed_get_bodies_are_kept() {
+        let raw = "GET /a HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi";
+        assert_eq!(HttpRequest::parse(raw).unwrap().body, b"hi");
+    }
+
+    #[test]
+    fn control_characters_in_the_target_are_rejected() {
+        assert!(HttpRequest::parse("GET /a\0b HTTP/1.1\r\n\r\n").is_none());
+        assert!(HttpRequest::parse("GET /a\x01b HTTP/1.1\r\n\r\n").is_none());
+        assert!(HttpRequest::parse("GET /a\x7fb HTTP/1.1\r\n\r\n").is_none());
+        // The query is never decoded, so only the check on the raw target sees these
+        assert!(HttpRequest::parse("GET /a?q=\x01 HTTP/1.1\r\n\r\n").is_none());
+        assert!(HttpRequest::parse("GET /a?q=\x7f HTTP/1.1\r\n\r\n").is_none());
+        assert!(HttpRequest::parse("GET /a\x1bb HTTP/1.1\r\n\r\n").is_none());
+        // An encoded one is refused just the same
+        assert!(HttpRequest::parse("GET /a%00b HTTP/1.1\r\n\r\n").is_none());
+        assert!(HttpRequest::parse("GET /a%20b HTTP/1.1\r\n\r\n").is_some());
+    }
+
+    // Inputs that once aborted or panicked; see fuzz/fuzz_targets/parse.rs
+    #[test]
+    fn huge_declared_sizes_fail_without_allocating() {
+        let huge_length = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nhi", usize::MAX);
+        assert!(HttpRequest::parse_bytes(huge_length.as_bytes()).is_none());
+        let huge_chunk = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nhi";
+        assert!(HttpRequest::parse_bytes(huge_chunk).is_none());
+    }
+
+    #[test]
+    fn every_truncation_of_a_request_parses_or_fails_cleanly() {
+        let request = b"POST /a?b=%41 HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n";
+        for end in 0..=request.len() {
+            let _ = HttpRequest::parse_bytes(&request[..end]);
+        }
+        for input in [&b""[..], b"\r\n", b"\n\n", b" ", b"GET", b"\xff\xfe\r\n\r\n", b"GET / HTTP/1.1\r\n:\r\n\r\n"] {
+            assert!(HttpRequest::parse_bytes(input).is_none(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn raw_target_keeps_escapes_that_path_decodes() {
+        let request = HttpRequest::parse("GET /search%20results?q=a%2Bb HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.raw_target, "/search%20results?q=a%2Bb");
+        assert_eq!(request.path, "/search results");
+        assert_eq!(request.query.as_deref(), Some("q=a%2Bb"));
+    }
+
+    #[test]
+    fn percent_decode_rejects_broken_escapes() {
+        assert_eq!(percent_decode("/a%2Fb+c").as_deref(), Some("/a/b+c"));
+        assert_eq!(percent_decode("/caf%C3%A9").as_deref(), Some("/café"));
+        assert_eq!(percent_decode("/a%2"), None);
+        assert_eq!(percent_decode("/a%zz"), None);
+        // Not UTF-8 once decoded
+        assert_eq!(percent_decode("/%FF"), None);
+    }
+
+    fn accepting(accept: &str) -> HttpRequest {
+        HttpRequest::parse(&format!("GET / HTTP/1.1\r\nAccept: {}\r\n\r\n", accept)).unwrap()
+    }
+
+    #[test]
+    fn preferred_follows_quality_and_specificity() {
+        let offers = ["application/json", "text/html"];
+        assert_eq!(accepting("text/html").preferred(&offers), Some("text/html"));
+        assert_eq!(accepting("text/*;q=0.5, application/json;q=0.4").preferred(&offers), Some("text/html"));
+        // The exact range outranks the wildcard that would allow it
+        assert_eq!(accepting("*/*, text/html;q=0").preferred(&offers), Some("application/json"));
+        assert_eq!(HttpRequest::parse("GET / HTTP/1.1\r\n\r\n").unwrap().preferred(&offers), Some("application/json"));
+    }
+
+    #[test]
+    fn preferred_is_none_when_nothing_is_acceptable() {
+        assert_eq!(accepting("text/html").preferred(&["application/json"]), None);
+        assert_eq!(accepting("application/json;q=0").preferred(&["application/json"]), None);
+    }
+
+    #[test]
+    fn an_endless_header_line_stops_at_the_limit() {
+        let mut head = b"GET / HTTP/1.1\r\nX-Long: ".to_vec();
+        head.extend(std::iter::repeat_n(b'a', 64 * 1024));
+        let mut reader = io::Cursor::new(head);
+        let result = HttpRequest::read_head_limited(&mut reader, 1024);
+        assert_eq!(result.err(), Some(HeadError::TooLarge));
+        // Nothing past the limit was consumed
+        assert!(reader.position() <= 1024);
+    }
+
+    #[test]
+    fn percent_encode_path_round_trips() {
+        for path in ["/items x", "/caf\u{e9}/%", "/a/b?c#d", "/plain-path_~"] {
+            assert_eq!(percent_decode(&percent_encode_path(path)).as_deref(), Some(path));
+        }
+        assert_eq!(percent_encode_path("/a b/c?d"), "/a%20b/c%3Fd");
+    }
+
+    fn form_request(body: &str) -> HttpRequest {
+        RequestBuilder::post("/form")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .build()
+    }
+
+    #[test]
+    fn form_multi_keeps_every_value() {
+        let fields = form_request("tags[]=a&tags[]=b&name=x").form_multi().unwrap();
+        assert_eq!(fields["tags"], ["a", "b"]);
+        assert_eq!(fields["name"], ["x"]);
+        assert_eq!(fields.len(), 2);
+
+        let fields = form_request("q=one+two&q=%26&empty=&flag").form_multi().unwrap();
+        assert_eq!(fields["q"], ["one two", "&"]);
+        assert_eq!((fields["empty"][0].as_str(), fields["flag"][0].as_str()), ("", ""));
+    }
+
+    #[test]
+    fn form_multi_needs_a_decodable_form() {
+        assert!(form_request("bad=%zz").form_multi().is_none());
+        assert!(RequestBuilder::post("/form").body("a=1").build().form_multi().is_none());
+    }
+
+    #[test]
+    fn targets_with_too_many_escapes_are_rejected() {
+        let target = |path: &str| HttpRequest::parse(&format!("GET {path} HTTP/1.1\r\n\r\n"));
+        let escaped = |count: usize| "%41".repeat(count);
+
+        let at_limit = target(&format!("/{}", escaped(MAX_TARGET_ESCAPES))).unwrap();
+        assert_eq!(at_limit.path.len(), 1 + MAX_TARGET_ESCAPES);
+        assert!(target(&format!("/{}", escaped(MAX_TARGET_ESCAPES + 1))).is_none());
+        assert!(target(&format!("/?q={}", escaped(500))).is_none());
+        // Counted separately, so each may use its full allowance
+        let both = format!("/{}?q={}", escaped(MAX_TARGET_ESCAPES), escaped(MAX_TARGET_ESCAPES));
+        assert!(target(&both).is_some());
+    }
+
+    #[test]
+    fn malformed_query_escapes_are_rejected() {
+        let parse = |target: &str| HttpRequest::parse(&format!("GET {target} HTTP/1.1\r\n\r\n"));
+        for target in ["/?a=%zz", "/?a=%2", "/?a=1&b=%", "/?%g0=x"] {
+            assert!(parse(target).is_none(), "{target} was accepted");
+        }
+        // Kept encoded, and not required to decode to UTF-8
+        let request = parse("/?a=%41%ff&b=%2F").unwrap();
+        assert_eq!(request.query.as_deref(), Some("a=%41%ff&b=%2F"));
+    }
+
+    fn chunked(raw: &[u8], max_trailer_bytes: usize) -> Result<(Vec<u8>, Fields), BodyError> {
+        read_chunked_limited(&mut io::Cursor::new(raw), 1 << 20, max_trailer_bytes)
+    }
+
+    #[test]
+    fn trailers_count_against_their_limit() {
+        let trailer = "x-checksum: abc\r\n";
+        let raw = format!("3\r\nabc\r\n0\r\n{}\r\n", trailer);
+        let (body, trailers) = chunked(raw.as_bytes(), trailer.len() + 2).unwrap();
+        assert_eq!((body.as_slice(), trailers.len()), (&b"abc"[..], 1));
+        assert_eq!(chunked(raw.as_bytes(), trailer.len() + 1), Err(BodyError::TrailersTooLarge));
+
+        // Many small lines add up just like one long one
+        let many = format!("0\r\n{}\r\n", "x-a: 1\r\n".repeat(2000));
+        assert_eq!(chunked(many.as_bytes(), 8192), Err(BodyError::TrailersTooLarge));
+        let endless = format!("0\r\nx-a: {}", "a".repeat(100_000));
+        assert_eq!(chunked(endless.as_bytes(), 8192), Err(BodyError::TrailersTooLarge));
+    }
+
+    #[test]
+    fn chunk_lines_are_bounded() {
+        let extension = format!("3;{}\r\nabc\r\n0\r\n\r\n", "e".repeat(MAX_CHUNK_LINE_BYTES));
+        assert_eq!(chunked(extension.as_bytes(), 8192), Err(BodyError::Malformed));
+        let short = "3;name=value\r\nabc\r\n0\r\n\r\n";
+        assert_eq!(chunked(short.as_bytes(), 8192).unwrap().0, b"abc");
+        // Data running on where the chunk's CRLF should be
+        let overrun = format!("3\r\nabc{}", "d".repeat(10_000));
+        assert_eq!(chunked(overrun.as_bytes(), 8192), Err(BodyError::Malformed));
+    }
+
+    fn stream_chunked(raw: &[u8], max_trailer_bytes: usize) -> io::Result<Vec<u8>> {
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let request = HttpRequest::parse(head).unwrap();
+        let mut reader = io::Cursor::new(raw);
+        let mut body = Vec::new();
+        request
+            .body_reader(&mut reader)
+            .unwrap()
+            .max_trailer_bytes(max_trailer_bytes)
+            .read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    #[test]
+    fn streamed_chunked_bodies_are_bounded_like_buffered_ones() {
+        let ok = "3\r\nabc\r\n0\r\nx-sum: 1\r\n\r\n";
+        assert_eq!(stream_chunked(ok.as_bytes(), 64).unwrap(), b"abc");
+
+        let trailers = format!("3\r\nabc\r\n0\r\n{}\r\n", "x-a: 1\r\n".repeat(100));
+        let error = stream_chunked(trailers.as_bytes(), 64).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let endless = format!("0\r\nx-a: {}", "a".repeat(100_000));
+        assert!(stream_chunked(endless.as_bytes(), DEFAULT_MAX_HEADER_BYTES).is_err());
+
+        let extension = format!("3;{}\r\nabc\r\n0\r\n\r\n", "e".repeat(100_000));
+        assert_eq!(stream_chunked(extension.as_bytes(), 64).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let overrun = format!("3\r\nabc{}", "d".repeat(100_000));
+        assert!(stream_chunked(overrun.as_bytes(), 64).is_err());
+    }
 }
@@ -1,37 +1,292 @@
 #![allow(dead_code)]
+
+// The server's own output: stdout and stderr by default, or records for the
+// log crate with the `log` feature, so it joins an existing logging setup
+macro_rules! warn_event {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        eprintln!($($arg)*);
+    }};
+}
+
+macro_rules! info_event {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::info!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        println!($($arg)*);
+    }};
+}
+
+macro_rules! debug_event {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        println!($($arg)*);
+    }};
+}
+
+// Per-job worker chatter: only worth emitting where a logger can filter it
+// out, so with the `log` feature alone; otherwise it would print on every job
+macro_rules! trace_event {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+pub mod access_log;
+pub mod cache;
+pub mod compression;
+pub mod date;
+pub mod forwarded;
+pub mod headers;
+pub mod http;
+pub mod metrics;
+pub mod middleware;
+pub mod proxy;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod sse;
+pub mod static_files;
+pub mod template;
+pub mod testing;
+
 use std::{
-    sync::{Arc, Mutex, mpsc},
+    io,
+    panic::{self, AssertUnwindSafe},
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
     thread,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    // Catch the panic, log it and keep the worker alive
+    #[default]
+    Isolate,
+    // Abort the whole process as soon as a job panics
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub workers: usize,
+    // Jobs rejected by try_execute because the queue was full
+    pub dropped: usize,
+}
+
+#[derive(Debug)]
+pub struct QueueFull;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PoolError {
+    // A pool needs at least one worker
+    ZeroSize,
+    // Spawning a worker thread failed
+    Spawn(String),
+}
+
+// Collects the pool options in one place:
+//
+//     let pool = ThreadPool::builder()
+//         .size(8)
+//         .queue_bound(128)
+//         .thread_name("http-worker")
+//         .panic_policy(PanicPolicy::Abort)
+//         .build()?;
+#[derive(Debug, Clone)]
+pub struct ThreadPoolBuilder {
+    size: usize,
+    queue_bound: Option<usize>,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+    panic_policy: PanicPolicy,
+}
+
+impl ThreadPoolBuilder {
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn queue_bound(mut self, queue_bound: usize) -> Self {
+        self.queue_bound = Some(queue_bound);
+        self
+    }
+
+    // Workers are named "<prefix>-<id>", which shows up in panic messages and debuggers
+    pub fn thread_name(mut self, prefix: &str) -> Self {
+        self.thread_name = Some(prefix.to_string());
+        self
+    }
+
+    // Bytes of stack per worker, for handlers that recurse deeply or keep large
+    // values on the stack. Defaults to the standard library's 2 MiB.
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    pub fn panic_policy(mut self, panic_policy: PanicPolicy) -> Self {
+        self.panic_policy = panic_policy;
+        self
+    }
+
+    pub fn build(self) -> Result<ThreadPool, PoolError> {
+        if self.size == 0 {
+            return Err(PoolError::ZeroSize);
+        }
+        ThreadPool::build(
+            self.size,
+            self.queue_bound,
+            self.thread_name.as_deref(),
+            self.stack_size,
+            self.panic_policy,
+        )
+    }
+}
+
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    sender: Option<JobSender>,
+    dropped: AtomicUsize,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+enum JobSender {
+    Unbounded(mpsc::Sender<Job>),
+    Bounded(mpsc::SyncSender<Job>),
+}
+
 impl ThreadPool {
+    // Panics if `size` is zero; use builder() to get an error instead
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        Self::with_panic_policy(size, PanicPolicy::default())
+    }
 
-        let (sender, receiver) = mpsc::channel();
+    // Panics if `size` is zero
+    pub fn with_panic_policy(size: usize, policy: PanicPolicy) -> ThreadPool {
+        Self::builder().size(size).panic_policy(policy).build().unwrap()
+    }
+
+    // At most `queue_bound` jobs wait for a free worker; beyond that try_execute fails.
+    // Panics if `size` is zero.
+    pub fn bounded(size: usize, queue_bound: usize) -> ThreadPool {
+        Self::builder().size(size).queue_bound(queue_bound).build().unwrap()
+    }
+
+    // Defaults to 4 workers, an unbounded queue and PanicPolicy::Isolate
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size: 4,
+            queue_bound: None,
+            thread_name: None,
+            stack_size: None,
+            panic_policy: PanicPolicy::default(),
+        }
+    }
+
+    fn build(
+        size: usize,
+        queue_bound: Option<usize>,
+        thread_name: Option<&str>,
+        stack_size: Option<usize>,
+        policy: PanicPolicy,
+    ) -> Result<ThreadPool, PoolError> {
+
+        let (sender, receiver) = match queue_bound {
+            Some(bound) => {
+                let (sender, receiver) = mpsc::sync_channel(bound);
+                (JobSender::Bounded(sender), receiver)
+            }
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                (JobSender::Unbounded(sender), receiver)
+            }
+        };
         let receiver = Arc::new(Mutex::new(receiver));
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            let mut builder = thread::Builder::new();
+            if let Some(prefix) = thread_name {
+                builder = builder.name(format!("{}-{}", prefix, id));
+            }
+            if let Some(stack_size) = stack_size {
+                builder = builder.stack_size(stack_size);
+            }
+            let worker = Worker::new(id, builder, Arc::clone(&receiver), policy)
+                .map_err(|e| PoolError::Spawn(e.to_string()))?;
+            workers.push(worker);
         }
 
-        ThreadPool { workers, sender }
+        Ok(ThreadPool {
+            workers,
+            sender: Some(sender),
+            dropped: AtomicUsize::new(0),
+        })
     }
 
+    // Blocks while a bounded queue is full
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
-        self.sender.send(job).unwrap();
+        match self.sender.as_ref().unwrap() {
+            JobSender::Unbounded(sender) => sender.send(job).unwrap(),
+            JobSender::Bounded(sender) => sender.send(job).unwrap(),
+        }
+    }
+
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFull>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+        match self.sender.as_ref().unwrap() {
+            JobSender::Unbounded(sender) => sender.send(job).unwrap(),
+            JobSender::Bounded(sender) => match sender.try_send(job) {
+                Ok(()) => {}
+                Err(mpsc::TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Err(QueueFull);
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => panic!("thread pool workers are gone"),
+            },
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            workers: self.workers.len(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel lets each worker finish its current job and exit
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            trace_event!("Shutting down worker {}", worker.id);
+            let _ = worker.thread.join();
+        }
     }
 }
 
@@ -41,13 +296,138 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
-            println!("Worker {id} got a job; executing.");
-            job();
+    fn new(
+        id: usize,
+        builder: thread::Builder,
+        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        policy: PanicPolicy,
+    ) -> io::Result<Worker> {
+        let thread = builder.spawn(move || loop {
+            let message = receiver.lock().unwrap().recv();
+            let Ok(job) = message else {
+                break;
+            };
+            trace_event!("Worker {id} got a job; executing.");
+
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                match policy {
+                    PanicPolicy::Isolate => warn_event!("Worker {id} recovered from a panicking job."),
+                    PanicPolicy::Abort => {
+                        warn_event!("Worker {id} job panicked; aborting.");
+                        process::abort();
+                    }
+                }
+            }
+        })?;
+
+        Ok(Worker { id, thread })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Duration;
+
+    #[test]
+    fn isolate_policy_keeps_the_pool_alive() {
+        let pool = ThreadPool::with_panic_policy(1, PanicPolicy::Isolate);
+        pool.execute(|| panic!("job failure"));
+
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(42).unwrap());
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(42));
+    }
+
+    // Runs only as the child process of abort_policy_propagates_the_panic
+    #[test]
+    fn abort_policy_child() {
+        if std::env::var_os("MTWS_ABORT_CHILD").is_none() {
+            return;
+        }
+        let pool = ThreadPool::with_panic_policy(1, PanicPolicy::Abort);
+        pool.execute(|| panic!("job failure"));
+        thread::sleep(Duration::from_secs(5));
+    }
+
+    #[test]
+    fn abort_policy_propagates_the_panic() {
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::abort_policy_child", "--nocapture"])
+            .env("MTWS_ABORT_CHILD", "1")
+            .status()
+            .unwrap();
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn try_execute_past_the_queue_bound_counts_drops() {
+        let pool = ThreadPool::bounded(1, 1);
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = wait_release.recv();
         });
+        wait_started.recv().unwrap();
+
+        // The worker is busy, so one job fits in the queue and the rest are dropped
+        assert!(pool.try_execute(|| {}).is_ok());
+        assert!(pool.try_execute(|| {}).is_err());
+        assert!(pool.try_execute(|| {}).is_err());
+        assert_eq!(pool.stats(), PoolStats { workers: 1, dropped: 2 });
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn builder_rejects_size_zero() {
+        assert_eq!(ThreadPool::builder().size(0).build().err(), Some(PoolError::ZeroSize));
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_panics_on_size_zero() {
+        ThreadPool::new(0);
+    }
 
-        Worker { id, thread }
+    #[test]
+    fn fully_configured_builder_builds() {
+        let pool = ThreadPool::builder()
+            .size(3)
+            .queue_bound(8)
+            .thread_name("test-worker")
+            .stack_size(4 * 1024 * 1024)
+            .panic_policy(PanicPolicy::Isolate)
+            .build()
+            .unwrap();
+        assert_eq!(pool.stats(), PoolStats { workers: 3, dropped: 0 });
+
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(thread::current().name().map(String::from)).unwrap());
+        let name = receiver.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert!(name.starts_with("test-worker-"), "{name}");
+    }
+
+    // Touches `depth` KiB of stack, more than the 2 MiB default allows for deep calls
+    fn recurse(depth: usize) -> u64 {
+        let frame = std::hint::black_box([depth as u8; 1024]);
+        if depth == 0 {
+            return frame[0] as u64;
+        }
+        recurse(depth - 1) + frame[1023] as u64
+    }
+
+    #[test]
+    fn a_larger_stack_fits_deep_recursion() {
+        let pool = ThreadPool::builder()
+            .size(1)
+            .stack_size(64 * 1024 * 1024)
+            .build()
+            .unwrap();
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(recurse(16 * 1024)).unwrap());
+        let expected: u64 = (1..=16 * 1024).map(|depth| (depth % 256) as u64).sum();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(30)), Ok(expected));
     }
 }
@@ -1,30 +1,25 @@
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
-use std::fs;
+use std::path::Path;
 use std::thread;
 use std::time::Duration;
-// use std::sync::Arc;
 
-mod http;
-mod response;
-mod router;
-
-use http::HttpRequest;
-use response::HttpResponse;
-use router::Router;
-use multi_threaded_web_server::ThreadPool;
+use multi_threaded_web_server::access_log::AccessLogFormat;
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::{Server, ServerConfig};
+use multi_threaded_web_server::static_files;
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
-
     // Create router with all your endpoints
-    let router = std::sync::Arc::new(Router::new()
-        .get("/", |_req| {
-            let contents = fs::read_to_string("hello.html").unwrap_or_else(|_| {
-                "<h1>Welcome to Rust Web Server!</h1>".to_string()
-            });
-            HttpResponse::ok().with_body(contents)
+    let router = Router::new()
+        .default_header("X-Content-Type-Options", "nosniff")
+        .default_header("X-Frame-Options", "DENY")
+        .get("/", |req| {
+            let response = static_files::serve_file(req, Path::new("hello.html"));
+            if response.status_code == 404 {
+                return HttpResponse::ok()
+                    .with_body("<h1>Welcome to Rust Web Server!</h1>".to_string());
+            }
+            response
         })
         .get("/sleep", |_req| {
             thread::sleep(Duration::from_secs(5));
@@ -43,54 +38,49 @@ fn main() {
             HttpResponse::json(200, "OK").with_body(users.to_string())
         })
         .post("/api/users", |req| {
-            println!("Received POST data: {}", req.body);
+            println!("Received POST data: {}", String::from_utf8_lossy(&req.body));
             let response = r#"{"message": "User created successfully", "id": 3}"#;
-            HttpResponse::json(201, "CREATED").with_body(response.to_string())
+            HttpResponse::created("/api/users/3")
+                .with_header("Content-Type", "application/json; charset=utf-8")
+                .with_body(response.to_string())
         })
         .put("/api/users/1", |req| {
-            println!("Updating user 1 with data: {}", req.body);
+            println!("Updating user 1 with data: {}", String::from_utf8_lossy(&req.body));
             let response = r#"{"message": "User updated successfully"}"#;
             HttpResponse::json(200, "OK").with_body(response.to_string())
         })
+        .patch("/api/users/:id", |req| {
+            let Some(id) = req.param("id").and_then(|id| id.parse::<u64>().ok()) else {
+                return HttpResponse::json_error(404, "no such user");
+            };
+            // Only the fields being changed are sent, as a JSON object
+            let changes = req.body_string().unwrap_or("").trim();
+            if !(changes.starts_with('{') && changes.ends_with('}')) {
+                return HttpResponse::json_error(400, "expected a JSON object of fields to change");
+            }
+            println!("Patching user {} with data: {}", id, changes);
+            let response = format!(
+                r#"{{"message": "User updated successfully", "id": {}, "changes": {}}}"#,
+                id, changes
+            );
+            HttpResponse::json(200, "OK").with_body(response)
+        })
         .delete("/api/users/1", |_req| {
             let response = r#"{"message": "User deleted successfully"}"#;
             HttpResponse::json(200, "OK").with_body(response.to_string())
-        }));
+        });
 
-    println!("Server running on http://127.0.0.1:7878");
+    let config = ServerConfig {
+        access_log: Some(AccessLogFormat::Common),
+        ..ServerConfig::default()
+    };
     println!("Available endpoints:");
-    println!("  GET  /");
-    println!("  GET  /sleep");
-    println!("  GET  /api/health");
-    println!("  GET  /api/users");
-    println!("  POST /api/users");
-    println!("  PUT  /api/users/1");
-    println!("  DELETE /api/users/1");
-
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        let router_clone = std::sync::Arc::clone(&router);
-        
-        pool.execute(move || {
-            handle_connection(stream, &router_clone);
-        });
+    for (method, path) in router.routes() {
+        println!("  {:<6} {}", method, path);
     }
-}
 
-fn handle_connection(mut stream: TcpStream, router: &Router) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
+    let server = Server::bind("127.0.0.1:7878", router, config).unwrap();
+    println!("Server running on http://127.0.0.1:7878");
 
-    let request_string = String::from_utf8_lossy(&buffer[..]);
-    
-    if let Some(request) = HttpRequest::parse(&request_string) {
-        let response = router.handle(&request);
-        stream.write(response.to_string().as_bytes()).unwrap();
-    } else {
-        let response = HttpResponse::bad_request()
-            .with_body("<h1>400 - Bad Request</h1>".to_string());
-        stream.write(response.to_string().as_bytes()).unwrap();
-    }
-    
-    stream.flush().unwrap();
+    server.run().unwrap();
 }
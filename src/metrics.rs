@@ -0,0 +1,110 @@
+#![allow(dead_code)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Request counters kept by a router with a metrics route, rendered in the
+// Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: AtomicUsize,
+    // Responses by status class, 1xx through 5xx
+    by_class: [AtomicUsize; 5],
+    open_connections: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn record(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if let Some(class) = self.by_class.get((status / 100).wrapping_sub(1) as usize) {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Counts a connection as open until the guard is dropped
+    pub fn connection(&self) -> ConnectionMetric<'_> {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionMetric(self)
+    }
+
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    // Responses whose status is in the given hundred, e.g. 2 for 2xx
+    pub fn responses(&self, class: u16) -> usize {
+        self.by_class
+            .get((class as usize).wrapping_sub(1))
+            .map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.open_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        text.push_str("# TYPE http_requests_total counter\n");
+        text.push_str(&format!("http_requests_total {}\n", self.requests()));
+        text.push_str("# TYPE http_responses_total counter\n");
+        for class in 1..=5 {
+            text.push_str(&format!(
+                "http_responses_total{{class=\"{}xx\"}} {}\n",
+                class,
+                self.responses(class)
+            ));
+        }
+        text.push_str("# TYPE http_open_connections gauge\n");
+        text.push_str(&format!("http_open_connections {}\n", self.open_connections()));
+        text
+    }
+}
+
+pub struct ConnectionMetric<'a>(&'a Metrics);
+
+impl Drop for ConnectionMetric<'_> {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_counted_by_class() {
+        let metrics = Metrics::default();
+        for status in [200, 204, 301, 404, 503, 99, 600] {
+            metrics.record(status);
+        }
+        assert_eq!(metrics.requests(), 7);
+        assert_eq!(metrics.responses(2), 2);
+        assert_eq!(metrics.responses(3), 1);
+        assert_eq!(metrics.responses(4), 1);
+        assert_eq!(metrics.responses(5), 1);
+        // Out-of-range statuses count as requests only
+        assert_eq!(metrics.responses(0), 0);
+        assert_eq!(metrics.responses(6), 0);
+    }
+
+    #[test]
+    fn connection_guards_track_open_connections() {
+        let metrics = Metrics::default();
+        let first = metrics.connection();
+        let second = metrics.connection();
+        assert_eq!(metrics.open_connections(), 2);
+        drop(first);
+        drop(second);
+        assert_eq!(metrics.open_connections(), 0);
+    }
+
+    #[test]
+    fn render_uses_the_prometheus_text_format() {
+        let metrics = Metrics::default();
+        metrics.record(200);
+        let text = metrics.render();
+        assert!(text.contains("# TYPE http_requests_total counter\nhttp_requests_total 1\n"));
+        assert!(text.contains("http_responses_total{class=\"2xx\"} 1\n"));
+        assert!(text.contains("http_responses_total{class=\"5xx\"} 0\n"));
+        assert!(text.ends_with("http_open_connections 0\n"));
+    }
+}
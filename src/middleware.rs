@@ -0,0 +1,298 @@
+#![allow(dead_code)]
+use crate::http::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::Next;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub struct HttpsRedirect {
+    // Scheme clients are redirected to
+    pub scheme: String,
+    // Honor X-Forwarded-Proto; only enable behind a proxy that sets it
+    pub trust_forwarded_proto: bool,
+    // Treat requests without a (trusted) X-Forwarded-Proto as plain http
+    pub assume_http: bool,
+    // Host every redirect points at. Without one the request's Host is used,
+    // but only if it's in allowed_hosts, since clients can send any Host and
+    // an open redirect would follow it.
+    pub canonical_host: Option<String>,
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for HttpsRedirect {
+    fn default() -> Self {
+        HttpsRedirect {
+            scheme: "https".to_string(),
+            trust_forwarded_proto: true,
+            assume_http: false,
+            canonical_host: None,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+pub fn https_redirect(
+    config: HttpsRedirect,
+) -> impl Fn(&mut HttpRequest, Next) -> HttpResponse + Send + Sync {
+    move |request, next| {
+        let forwarded_proto = if config.trust_forwarded_proto {
+            request.headers.get("x-forwarded-proto")
+        } else {
+            None
+        };
+
+        // With several proxies in front the first entry is the client's scheme
+        let is_http = match forwarded_proto {
+            Some(proto) => proto
+                .split(',')
+                .next()
+                .is_some_and(|proto| proto.trim().eq_ignore_ascii_case("http")),
+            None => config.assume_http,
+        };
+
+        if !is_http {
+            return next(request);
+        }
+
+        let host = match &config.canonical_host {
+            Some(host) => Some(host.as_str()),
+            None => request.headers.get("host").map(String::as_str).filter(|host| {
+                config
+                    .allowed_hosts
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(host))
+            }),
+        };
+
+        match host {
+            Some(host) => {
+                let location = format!("{}://{}{}", config.scheme, host, request.raw_target);
+                HttpResponse::permanent_redirect(&location)
+            }
+            None => HttpResponse::bad_request()
+                .with_body("<h1>400 - Bad Request</h1>".to_string()),
+        }
+    }
+}
+
+pub struct Maintenance {
+    // Shared with whatever toggles maintenance at runtime (a handler, a signal thread)
+    pub enabled: Arc<AtomicBool>,
+    // Seconds clients are told to wait via Retry-After
+    pub retry_after: u64,
+    pub body: String,
+    // Paths still served normally, e.g. the health check load balancers poll
+    pub allowed_paths: Vec<String>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            enabled: Arc::new(AtomicBool::new(false)),
+            retry_after: 120,
+            body: "<h1>503 - Down for maintenance</h1>".to_string(),
+            allowed_paths: vec!["/api/health".to_string()],
+        }
+    }
+}
+
+pub fn maintenance_mode(
+    config: Maintenance,
+) -> impl Fn(&mut HttpRequest, Next) -> HttpResponse + Send + Sync {
+    move |request, next| {
+        if !config.enabled.load(Ordering::Relaxed)
+            || config.allowed_paths.contains(&request.path)
+        {
+            return next(request);
+        }
+
+        HttpResponse::service_unavailable()
+            .with_header("Retry-After", &config.retry_after.to_string())
+            .with_body(config.body.clone())
+    }
+}
+
+// Caps the requests one client IP can have in flight at once, answering any
+// beyond `max_per_ip` with a 429 so a single client can't tie up every worker
+// with slow requests. Requests without a known client address aren't limited.
+pub fn concurrency_limit(
+    max_per_ip: usize,
+) -> impl Fn(&mut HttpRequest, Next) -> HttpResponse + Send + Sync {
+    let in_flight: Arc<Mutex<HashMap<IpAddr, usize>>> = Arc::new(Mutex::new(HashMap::new()));
+    move |request, next| {
+        let Some(ip) = request.client_ip else {
+            return next(request);
+        };
+
+        {
+            let mut in_flight = in_flight.lock().unwrap();
+            let count = in_flight.entry(ip).or_insert(0);
+            if *count >= max_per_ip {
+                return HttpResponse::too_many_requests()
+                    .with_body("<h1>429 - Too Many Requests</h1>".to_string());
+            }
+            *count += 1;
+        }
+        // Released on drop so a panicking handler doesn't leak its slot
+        let _slot = InFlight {
+            counts: Arc::clone(&in_flight),
+            ip,
+        };
+        next(request)
+    }
+}
+
+struct InFlight {
+    counts: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    ip: IpAddr,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+// Logs each request and response body, cut off after `max_bytes`, for seeing
+// exactly what goes over the wire while developing. Streamed bodies aren't
+// buffered and are only noted. Request bodies of post_stream and put_stream
+// routes aren't seen. Not meant for production, where bodies can hold secrets.
+pub fn body_log(max_bytes: usize) -> impl Fn(&mut HttpRequest, Next) -> HttpResponse + Send + Sync {
+    move |request, next| {
+        let target = request.raw_target.clone();
+        debug_event!("> {} {} {}", request.method, target, snippet(&request.body, max_bytes));
+        let response = next(request);
+        let body = if response.is_streaming() {
+            "(streamed)".to_string()
+        } else {
+            snippet(&response.body, max_bytes)
+        };
+        debug_event!("< {} {} {}", response.status_code, target, body);
+        response
+    }
+}
+
+// The start of `body` on one line, with how much was left out
+fn snippet(body: &[u8], max_bytes: usize) -> String {
+    let shown = String::from_utf8_lossy(&body[..body.len().min(max_bytes)]);
+    let mut snippet = format!("{:?}", shown);
+    if body.len() > max_bytes {
+        snippet.push_str(&format!(" (+{} bytes)", body.len() - max_bytes));
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+
+    fn ok(_: &mut HttpRequest) -> HttpResponse {
+        HttpResponse::ok().with_body("<h1>ok</h1>".to_string())
+    }
+
+    fn redirect(config: HttpsRedirect, request: RequestBuilder) -> HttpResponse {
+        https_redirect(config)(&mut request.build(), &ok)
+    }
+
+    #[test]
+    fn forwarded_http_gets_a_308_to_https() {
+        let config = HttpsRedirect {
+            canonical_host: Some("example.com".to_string()),
+            ..HttpsRedirect::default()
+        };
+        let request = RequestBuilder::get("/login?next=%2F")
+            .header("Host", "example.com")
+            .header("X-Forwarded-Proto", "http");
+        let response = redirect(config, request);
+        assert_eq!(response.status_code, 308);
+        assert_eq!(response.header("Location"), Some("https://example.com/login?next=%2F"));
+    }
+
+    #[test]
+    fn forwarded_https_passes_through() {
+        let config = HttpsRedirect {
+            canonical_host: Some("example.com".to_string()),
+            ..HttpsRedirect::default()
+        };
+        let request = RequestBuilder::get("/").header("X-Forwarded-Proto", "https");
+        assert_eq!(redirect(config, request).status_code, 200);
+    }
+
+    #[test]
+    fn canonical_host_ignores_the_host_header() {
+        let config = HttpsRedirect {
+            canonical_host: Some("example.com".to_string()),
+            ..HttpsRedirect::default()
+        };
+        let request = RequestBuilder::get("/")
+            .header("Host", "evil.test")
+            .header("X-Forwarded-Proto", "http");
+        let response = redirect(config, request);
+        assert_eq!(response.header("Location"), Some("https://example.com/"));
+    }
+
+    #[test]
+    fn unlisted_host_is_not_redirected_to() {
+        let config = HttpsRedirect {
+            allowed_hosts: vec!["example.com".to_string()],
+            ..HttpsRedirect::default()
+        };
+        let request = RequestBuilder::get("/")
+            .header("Host", "evil.test")
+            .header("X-Forwarded-Proto", "http");
+        assert_eq!(redirect(config, request).status_code, 400);
+
+        let config = HttpsRedirect {
+            allowed_hosts: vec!["example.com".to_string()],
+            ..HttpsRedirect::default()
+        };
+        let request = RequestBuilder::get("/")
+            .header("Host", "Example.com")
+            .header("X-Forwarded-Proto", "http");
+        let response = redirect(config, request);
+        assert_eq!(response.header("Location"), Some("https://Example.com/"));
+    }
+
+    #[test]
+    fn maintenance_mode_spares_allowed_paths() {
+        let enabled = Arc::new(AtomicBool::new(false));
+        let maintenance = maintenance_mode(Maintenance {
+            enabled: Arc::clone(&enabled),
+            retry_after: 60,
+            ..Maintenance::default()
+        });
+        let call = |path: &str| maintenance(&mut RequestBuilder::get(path).build(), &ok);
+
+        assert_eq!(call("/api/users").status_code, 200);
+
+        enabled.store(true, Ordering::Relaxed);
+        let response = call("/api/users");
+        assert_eq!(response.status_code, 503);
+        assert_eq!(response.header("Retry-After"), Some("60"));
+        assert_eq!(call("/api/health").status_code, 200);
+    }
+
+    #[test]
+    fn body_snippets_are_cut_off_at_the_limit() {
+        assert_eq!(snippet(b"name=x", 64), r#""name=x""#);
+        assert_eq!(snippet(b"line one\nline two", 8), r#""line one" (+9 bytes)"#);
+        assert_eq!(snippet(b"", 8), r#""""#);
+    }
+
+    #[test]
+    fn body_log_leaves_the_response_alone() {
+        let mut request = RequestBuilder::post("/echo").body("payload").build();
+        let logged = body_log(3)(&mut request, &ok);
+        let direct = ok(&mut RequestBuilder::post("/echo").body("payload").build());
+        assert_eq!((logged.status_code, &logged.headers, &logged.body), (direct.status_code, &direct.headers, &direct.body));
+    }
+}
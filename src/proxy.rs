@@ -0,0 +1,258 @@
+#![allow(dead_code)]
+use crate::http::{
+    HttpMethod, HttpRequest, parse_content_length, percent_decode, percent_encode_path, read_bytes,
+    read_chunked,
+};
+use crate::response::HttpResponse;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Headers that only describe a single connection and must not be forwarded
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// An upstream given as "http://host:port/base"; only plain HTTP is supported
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    authority: String,
+    base_path: String,
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Option<Upstream> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, base_path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return None;
+        }
+
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Some(Upstream {
+            authority,
+            base_path: base_path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+// The target to send upstream: the path routing matched, which a rewrite may
+// have changed, minus `prefix`, under `base_path`. A path no rewrite touched
+// keeps the encoding it was sent with; the query always does.
+fn upstream_target(request: &HttpRequest, prefix: &str, base_path: &str) -> String {
+    let raw_path = request.raw_target.split('?').next().unwrap_or("");
+    let unchanged = percent_decode(raw_path).as_deref() == Some(request.path.as_str());
+    let rest = match raw_path.strip_prefix(prefix) {
+        Some(rest) if unchanged => rest.to_string(),
+        _ => percent_encode_path(request.path.strip_prefix(prefix).unwrap_or(&request.path)),
+    };
+
+    let mut target = format!("{}{}", base_path, rest);
+    if !target.starts_with('/') {
+        target.insert(0, '/');
+    }
+    if let Some(query) = &request.query {
+        target.push('?');
+        target.push_str(query);
+    }
+    target
+}
+
+// Forwards `request` with `prefix` removed from its path and relays the answer.
+// Any failure talking to the upstream becomes a 502.
+pub fn forward(request: &HttpRequest, prefix: &str, upstream: &Upstream) -> HttpResponse {
+    match try_forward(request, prefix, upstream) {
+        Ok(response) => response,
+        Err(e) => {
+            warn_event!("Proxy to {} failed: {e}", upstream.authority);
+            HttpResponse::bad_gateway().with_body("<h1>502 - Bad Gateway</h1>".to_string())
+        }
+    }
+}
+
+fn try_forward(request: &HttpRequest, prefix: &str, upstream: &Upstream) -> io::Result<HttpResponse> {
+    let stream = TcpStream::connect(&upstream.authority)?;
+    stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+
+    let path = upstream_target(request, prefix, &upstream.base_path);
+
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method, path);
+    let dropped = connection_listed(request.headers.get("connection").map(String::as_str));
+    for (key, value) in &request.headers {
+        // The body has already been read, so there's nothing left to expect
+        let skip = matches!(key.as_str(), "host" | "content-length" | "x-forwarded-for" | "expect")
+            || HOP_BY_HOP.contains(&key.as_str())
+            || dropped.contains(key);
+        if !skip {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+    }
+    head.push_str(&format!("host: {}\r\n", upstream.authority));
+    if let Some(forwarded_for) = forwarded_for(request) {
+        head.push_str(&format!("x-forwarded-for: {}\r\n", forwarded_for));
+    }
+    head.push_str(&format!("content-length: {}\r\n", request.body.len()));
+    head.push_str("connection: close\r\n\r\n");
+
+    let mut writer = &stream;
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(&request.body)?;
+    writer.flush()?;
+
+    read_response(&mut BufReader::new(&stream), request)
+}
+
+// Appends the client to any X-Forwarded-For chain it arrived with
+fn forwarded_for(request: &HttpRequest) -> Option<String> {
+    let peer = request.peer_addr.map(|addr| addr.ip().to_string());
+    match (request.headers.get("x-forwarded-for"), peer) {
+        (Some(chain), Some(peer)) => Some(format!("{}, {}", chain, peer)),
+        (Some(chain), None) => Some(chain.clone()),
+        (None, peer) => peer,
+    }
+}
+
+// Header names a Connection header asks to be removed along with it
+fn connection_listed(connection: Option<&str>) -> Vec<String> {
+    connection
+        .unwrap_or("")
+        .split(',')
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+fn read_response<R: BufRead>(reader: &mut R, request: &HttpRequest) -> io::Result<HttpResponse> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed upstream response");
+
+    // Interim 1xx responses, such as a 100 Continue, come before the final one
+    let (status_code, status_text, headers) = loop {
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line)?;
+        let mut parts = status_line.trim_end().splitn(3, ' ');
+        let _version = parts.next().ok_or_else(invalid)?;
+        let status_code: u16 = parts.next().and_then(|code| code.parse().ok()).ok_or_else(invalid)?;
+        let status_text = parts.next().unwrap_or("").to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid());
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line.split_once(':').ok_or_else(invalid)?;
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+        if !(100..=199).contains(&status_code) {
+            break (status_code, status_text, headers);
+        }
+    };
+
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let no_body = request.method == HttpMethod::HEAD
+        || matches!(status_code, 100..=199 | 204 | 304);
+    let body = if no_body {
+        Vec::new()
+    } else if header("transfer-encoding").is_some_and(|value| value.eq_ignore_ascii_case("chunked")) {
+        read_chunked(reader).ok_or_else(invalid)?.0
+    } else if let Some(length) = header("content-length") {
+        read_bytes(reader, parse_content_length(length).ok_or_else(invalid)? as u64)?
+    } else {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        body
+    };
+
+    let dropped = connection_listed(header("connection"));
+    let mut response = HttpResponse::new(status_code, &status_text);
+    response.headers.clear();
+    for (key, value) in &headers {
+        let lower = key.to_ascii_lowercase();
+        // to_bytes writes the length of the body actually relayed
+        let replaced = lower == "content-length" && !no_body;
+        if !HOP_BY_HOP.contains(&lower.as_str()) && !dropped.contains(&lower) && !replaced {
+            response = response.with_header(key, value);
+        }
+    }
+    if no_body {
+        response.no_body = true;
+        Ok(response)
+    } else {
+        Ok(response.with_bytes(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+
+    fn target(raw: &str, rewritten: Option<&str>, prefix: &str, base_path: &str) -> String {
+        let mut request = RequestBuilder::get(raw).build();
+        if let Some(path) = rewritten {
+            request.path = path.to_string();
+        }
+        upstream_target(&request, prefix, base_path)
+    }
+
+    #[test]
+    fn an_untouched_path_keeps_its_encoding() {
+        assert_eq!(target("/api/a%2Fb?q=%41", None, "/api", "/backend"), "/backend/a%2Fb?q=%41");
+        assert_eq!(target("/api", None, "/api", ""), "/");
+        assert_eq!(target("/api?x=1", None, "/api", ""), "/?x=1");
+    }
+
+    #[test]
+    fn a_rewritten_path_is_forwarded_encoded() {
+        let forwarded = target("/v1/api/items%20x?page=2", Some("/api/items x"), "/api", "");
+        assert_eq!(forwarded, "/items%20x?page=2");
+        // The raw target still has the prefix the rewrite removed
+        assert_eq!(target("/v1/api/users", Some("/api/users"), "/api", "/b"), "/b/users");
+    }
+
+    #[test]
+    fn upstream_urls_parse() {
+        let upstream = Upstream::parse("http://backend/base/").unwrap();
+        assert_eq!((upstream.authority.as_str(), upstream.base_path.as_str()), ("backend:80", "/base"));
+        assert!(Upstream::parse("https://backend").is_none());
+        assert!(Upstream::parse("http:///path").is_none());
+    }
+
+    #[test]
+    fn interim_responses_are_skipped() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\n\
+                    HTTP/1.1 201 Created\r\nContent-Length: 4\r\nX-Final: yes\r\n\r\ndone";
+        let request = RequestBuilder::post("/api").build();
+        let response = read_response(&mut io::Cursor::new(&raw[..]), &request).unwrap();
+        assert_eq!((response.status_code, response.status_text.as_str()), (201, "Created"));
+        assert_eq!(response.header("X-Final"), Some("yes"));
+        assert_eq!(response.header("Link"), None);
+        assert_eq!(response.body, b"done");
+    }
+}
@@ -1,23 +1,107 @@
 #![allow(dead_code)]
+use crate::access_log::escape_json;
+use crate::headers::MediaType;
+use crate::static_files::content_type;
 use std::collections::HashMap;
+use std::any::Any;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+type ChunkIter = Box<dyn Iterator<Item = Vec<u8>> + Send>;
+
+// A body produced piece by piece. Shared so responses stay Clone, but only
+// the first write drains it.
+#[derive(Clone)]
+pub struct Chunks(Arc<Mutex<Option<ChunkIter>>>);
+
+impl fmt::Debug for Chunks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Chunks(..)")
+    }
+}
+
+// Cloning copies a buffered body. A streamed body (from_chunks) is shared
+// instead, since an iterator can't be replayed: whichever copy is written
+// first sends the chunks. Check is_streaming() before keeping a copy around.
+#[derive(Debug, Clone)]
 pub struct HttpResponse {
     pub status_code: u16,
     pub status_text: String,
     pub headers: HashMap<String, String>,
-    pub body: String,
+    pub body: Vec<u8>,
+    // Sent with chunked transfer encoding after the headers, in place of `body`
+    pub chunks: Option<Chunks>,
+    // Set for HEAD and 304 responses: the headers are sent as they are, with
+    // whatever Content-Length they announce, and the body never is
+    pub no_body: bool,
+    // The error a handler returned, for the router's error handlers to map
+    pub error: Option<Arc<dyn Any + Send + Sync>>,
+    // A whole response serialized ahead of time, written as is in place of
+    // everything above (see from_prebuilt)
+    pub prebuilt: Option<Arc<Vec<u8>>>,
 }
 
 impl HttpResponse {
     pub fn new(status_code: u16, status_text: &str) -> Self {
         let mut headers = HashMap::new();
-        headers.insert("Content-Type".to_string(), "text/html".to_string());
+        headers.insert("Content-Type".to_string(), "text/html; charset=utf-8".to_string());
         
         HttpResponse {
             status_code,
             status_text: status_text.to_string(),
             headers,
-            body: String::new(),
+            body: Vec::new(),
+            chunks: None,
+            no_body: false,
+            error: None,
+            prebuilt: None,
+        }
+    }
+
+    // Answers with `bytes`, a complete response from an earlier to_bytes(),
+    // without assembling headers each time, for endpoints whose answer never
+    // changes. The bytes go out verbatim: default headers, error pages and the
+    // server's Server, Connection and Keep-Alive headers aren't added. Without
+    // the latter two the client can't be told the connection stays open, so
+    // the server closes it after the bytes. status_code is read from them.
+    //
+    //     let health = Arc::new(HttpResponse::json(200, "OK").with_body(body).to_bytes());
+    //     router.get("/health", move |_| HttpResponse::from_prebuilt(Arc::clone(&health)))
+    pub fn from_prebuilt(bytes: Arc<Vec<u8>>) -> Self {
+        let status_line = bytes.split(|&b| b == b'\r').next().unwrap_or(&[]);
+        let status_line = String::from_utf8_lossy(status_line);
+        let mut parts = status_line.splitn(3, ' ').skip(1);
+        let status_code = parts.next().and_then(|code| code.parse().ok()).unwrap_or(200);
+
+        let mut response = Self::new(status_code, parts.next().unwrap_or(""));
+        response.headers.clear();
+        response.prebuilt = Some(bytes);
+        response
+    }
+
+    // Drops the body but keeps the headers, with the Content-Length it would
+    // have had, as a response to HEAD. A Content-Length set on an empty body,
+    // as a HEAD handler does to report a file's size, is kept as it is.
+    pub(crate) fn clear_body(&mut self) {
+        let announced = self.body.is_empty() && self.has_header("Content-Length");
+        if !self.no_body && !self.is_streaming() && self.has_length() && !announced {
+            let length = self.body.len().to_string();
+            self.headers.retain(|key, _| !key.eq_ignore_ascii_case("content-length"));
+            self.headers.insert("Content-Length".to_string(), length);
+        }
+        self.no_body = true;
+        self.body.clear();
+        self.chunks = None;
+        if let Some(bytes) = &self.prebuilt {
+            let head_end = bytes
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .map_or(bytes.len(), |end| end + 4);
+            self.prebuilt = Some(Arc::new(bytes[..head_end].to_vec()));
         }
     }
 
@@ -25,40 +109,774 @@ impl HttpResponse {
         Self::new(200, "OK")
     }
 
+    pub fn created(location: &str) -> Self {
+        Self::new(201, "CREATED").with_header("Location", location)
+    }
+
+    pub fn permanent_redirect(location: &str) -> Self {
+        Self::new(308, "PERMANENT REDIRECT").with_header("Location", location)
+    }
+
+    pub fn not_modified() -> Self {
+        let mut response = Self::new(304, "NOT MODIFIED");
+        response.no_body = true;
+        response
+    }
+
     pub fn not_found() -> Self {
         Self::new(404, "NOT FOUND")
     }
 
+    pub fn method_not_allowed() -> Self {
+        Self::new(405, "METHOD NOT ALLOWED")
+    }
+
     pub fn bad_request() -> Self {
         Self::new(400, "BAD REQUEST")
     }
 
+    pub fn payload_too_large() -> Self {
+        Self::new(413, "PAYLOAD TOO LARGE")
+    }
+
+    // For a request whose Accept header rules out every representation on offer
+    pub fn not_acceptable() -> Self {
+        Self::new(406, "NOT ACCEPTABLE")
+    }
+
+    pub fn unsupported_media_type() -> Self {
+        Self::new(415, "UNSUPPORTED MEDIA TYPE")
+    }
+
+    pub fn expectation_failed() -> Self {
+        Self::new(417, "EXPECTATION FAILED")
+    }
+
+    pub fn too_many_requests() -> Self {
+        Self::new(429, "TOO MANY REQUESTS")
+    }
+
+    pub fn request_header_fields_too_large() -> Self {
+        Self::new(431, "REQUEST HEADER FIELDS TOO LARGE")
+    }
+
+    pub fn unavailable_for_legal_reasons() -> Self {
+        Self::new(451, "UNAVAILABLE FOR LEGAL REASONS")
+    }
+
+    pub fn internal_server_error() -> Self {
+        Self::new(500, "INTERNAL SERVER ERROR")
+    }
+
+    // For a request using a feature the server doesn't support, such as a
+    // transfer coding other than chunked
+    pub fn not_implemented() -> Self {
+        Self::new(501, "NOT IMPLEMENTED")
+    }
+
+    pub fn bad_gateway() -> Self {
+        Self::new(502, "BAD GATEWAY")
+    }
+
+    pub fn service_unavailable() -> Self {
+        Self::new(503, "SERVICE UNAVAILABLE")
+    }
+
+    pub fn gateway_timeout() -> Self {
+        Self::new(504, "GATEWAY TIMEOUT")
+    }
+
     pub fn json(status_code: u16, status_text: &str) -> Self {
         let mut response = Self::new(status_code, status_text);
-        response.headers.insert("Content-Type".to_string(), "application/json".to_string());
+        response.headers.insert(
+            "Content-Type".to_string(),
+            "application/json; charset=utf-8".to_string(),
+        );
         response
     }
 
-    pub fn with_body(mut self, body: String) -> Self {
-        self.body = body.clone();
-        self.headers.insert("Content-Length".to_string(), body.len().to_string());
+    // {"error": "<message>", "status": <code>} for API consumers that expect JSON
+    pub fn json_error(status_code: u16, message: &str) -> Self {
+        Self::json(status_code, status_text(status_code)).with_body(format!(
+            r#"{{"error": "{}", "status": {}}}"#,
+            escape_json(message),
+            status_code
+        ))
+    }
+
+    pub fn html(body: String) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    pub fn text(body: String) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    // Streams each piece as its own chunk, so the full body never has to be in memory
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        let mut response = Self::ok().with_header("Transfer-Encoding", "chunked");
+        response.chunks = Some(Chunks(Arc::new(Mutex::new(Some(Box::new(chunks.into_iter()))))));
+        response
+    }
+
+    // Streams the file at `path` as an attachment saved under `filename`,
+    // reading it a piece at a time. The length is known up front, so the body
+    // goes out as-is rather than chunked.
+    pub fn download(path: &Path, filename: &str) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        // Never send more than announced, even if the file grows meanwhile
+        let mut file = file.take(length);
+        let pieces = std::iter::from_fn(move || {
+            let mut piece = vec![0; 64 * 1024];
+            match file.read(&mut piece) {
+                Ok(0) | Err(_) => None,
+                Ok(read) => {
+                    piece.truncate(read);
+                    Some(piece)
+                }
+            }
+        });
+
+        let mut response = Self::from_chunks(pieces);
+        response.headers.remove("Transfer-Encoding");
+        Ok(response
+            .with_header("Content-Type", content_type(path))
+            .with_header("Content-Length", &length.to_string())
+            .with_header("Content-Disposition", &content_disposition(filename)))
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.chunks.is_some()
+    }
+
+    pub fn with_body(self, body: String) -> Self {
+        self.with_bytes(body.into_bytes())
+    }
+
+    // The Content-Length is left to to_bytes, which takes it from the body
+    pub fn with_bytes(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
         self
     }
 
+    // Replaces the body with `f` applied to it
+    pub fn map_body<F: FnOnce(Vec<u8>) -> Vec<u8>>(mut self, f: F) -> Self {
+        let body = std::mem::take(&mut self.body);
+        self.with_bytes(f(body))
+    }
+
+    // Header names are case-insensitive, so a new value replaces any existing
+    // spelling of the same header instead of sending both
     pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case(key));
         self.headers.insert(key.to_string(), value.to_string());
         self
     }
 
-    pub fn to_string(&self) -> String {
+    // Adds `field` to the Vary header, keeping whatever it already lists
+    pub fn with_vary(self, field: &str) -> Self {
+        let existing = self.header("Vary").unwrap_or("").to_string();
+        let listed = existing
+            .split(',')
+            .map(str::trim)
+            .any(|name| name == "*" || name.eq_ignore_ascii_case(field));
+        if listed {
+            return self;
+        }
+        let value = if existing.trim().is_empty() {
+            field.to_string()
+        } else {
+            format!("{}, {}", existing.trim(), field)
+        };
+        self.with_header("Vary", &value)
+    }
+
+    pub fn with_hsts(self, max_age: u64, include_subdomains: bool, preload: bool) -> Self {
+        let mut value = format!("max-age={}", max_age);
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if preload {
+            value.push_str("; preload");
+        }
+        self.with_header("Strict-Transport-Security", &value)
+    }
+
+    // Names who demanded the block, for a 451 (RFC 7725)
+    pub fn with_blocked_by(self, authority: &str) -> Self {
+        self.with_header("Link", &format!("<{}>; rel=\"blocked-by\"", authority))
+    }
+
+    pub fn with_content_type(self, media_type: &MediaType) -> Self {
+        self.with_header("Content-Type", &media_type.to_string())
+    }
+
+    // For dynamic responses no cache along the way should keep
+    pub fn with_no_cache(self) -> Self {
+        self.with_header("Cache-Control", "no-store")
+            .with_header("Pragma", "no-cache")
+    }
+
+    // Lets browsers and shared caches reuse the response for `duration`
+    pub fn with_cache_for(self, duration: Duration) -> Self {
+        self.with_header(
+            "Cache-Control",
+            &format!("public, max-age={}", duration.as_secs()),
+        )
+    }
+
+    // Replaces the charset parameter of the current Content-Type
+    pub fn with_charset(self, charset: &str) -> Self {
+        let media_type = self
+            .header("Content-Type")
+            .and_then(|value| value.split(';').next())
+            .unwrap_or("text/plain")
+            .trim()
+            .to_string();
+        self.with_header("Content-Type", &format!("{}; charset={}", media_type, charset))
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn has_header(&self, key: &str) -> bool {
+        self.header(key).is_some()
+    }
+
+    // Whether a buffered body is sent with a Content-Length; never for 1xx,
+    // 204 and 304, which can't have a body
+    fn has_length(&self) -> bool {
+        !matches!(self.status_code, 100..=199 | 204 | 304)
+    }
+
+    // A buffered body always goes out with a Content-Length written from the
+    // body itself, whatever the headers say, so code that edits `body`
+    // directly can't send a stale one. Streamed and no_body responses keep
+    // the headers as set.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if let Some(bytes) = &self.prebuilt {
+            return bytes.to_vec();
+        }
         let mut response = format!("HTTP/1.1 {} {}\r\n", self.status_code, self.status_text);
-        
+        let computed = !self.no_body && !self.is_streaming() && self.has_length();
+
         for (key, value) in &self.headers {
-            response.push_str(&format!("{}: {}\r\n", key, value));
+            if !(computed && key.eq_ignore_ascii_case("content-length")) {
+                response.push_str(&format!("{}: {}\r\n", key, value));
+            }
         }
-        
+        if computed {
+            response.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+
         response.push_str("\r\n");
-        response.push_str(&self.body);
-        response
+        let mut bytes = response.into_bytes();
+        if !self.no_body {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+
+    // Like writing to_bytes(), but also drives a chunked body to completion,
+    // flushing after every chunk so a buffered writer such as BufWriter can't
+    // hold an event back. Each chunk goes out in a single write, since Nagle's
+    // algorithm can delay the second of two small writes on an unbuffered socket.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if let Some(bytes) = &self.prebuilt {
+            writer.write_all(bytes)?;
+            return writer.flush();
+        }
+        writer.write_all(&self.to_bytes())?;
+
+        let chunks = self.chunks.as_ref().and_then(|chunks| chunks.0.lock().unwrap().take());
+        if let Some(chunks) = chunks {
+            // A stream with a known length (download) is sent as plain bytes
+            if !self.has_header("Transfer-Encoding") {
+                for chunk in chunks {
+                    writer.write_all(&chunk)?;
+                }
+                return writer.flush();
+            }
+
+            writer.flush()?;
+            for chunk in chunks {
+                // A zero-length chunk would end the body early
+                if chunk.is_empty() {
+                    continue;
+                }
+                let mut frame = format!("{:x}\r\n", chunk.len()).into_bytes();
+                frame.extend_from_slice(&chunk);
+                frame.extend_from_slice(b"\r\n");
+                writer.write_all(&frame)?;
+                writer.flush()?;
+            }
+            writer.write_all(b"0\r\n\r\n")?;
+        }
+
+        writer.flush()
+    }
+}
+
+// `attachment; filename="..."`, escaping quotes and backslashes. A name with
+// non-ASCII characters also gets an RFC 5987 `filename*`, with `_` standing in
+// for those characters in the plain fallback.
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '_' })
+        .collect();
+    let quoted = fallback.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut value = format!("attachment; filename=\"{}\"", quoted);
+
+    if fallback != filename {
+        let mut encoded = String::new();
+        for byte in filename.bytes() {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        value.push_str(&format!("; filename*=UTF-8''{}", encoded));
+    }
+    value
+}
+
+// Lets handlers return plain values instead of building an HttpResponse
+pub trait IntoResponse {
+    fn into_response(self) -> HttpResponse;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self) -> HttpResponse {
+        self
+    }
+}
+
+// 200 with a text/plain body
+impl IntoResponse for String {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::text(self)
+    }
+}
+
+impl IntoResponse for &str {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::text(self.to_string())
+    }
+}
+
+// 200 with an application/octet-stream body
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::ok()
+            .with_header("Content-Type", "application/octet-stream")
+            .with_bytes(self)
+    }
+}
+
+// A text/plain body with the given status code
+impl IntoResponse for (u16, String) {
+    fn into_response(self) -> HttpResponse {
+        let (status_code, body) = self;
+        HttpResponse::new(status_code, status_text(status_code))
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+}
+
+// Errors become a 500 unless an error handler registered on the router for
+// their type maps them to something better
+impl<R, E> IntoResponse for Result<R, E>
+where
+    R: IntoResponse,
+    E: Send + Sync + 'static,
+{
+    fn into_response(self) -> HttpResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(error) => {
+                let mut response = HttpResponse::internal_server_error()
+                    .with_body("<h1>500 - Internal Server Error</h1>".to_string());
+                response.error = Some(Arc::new(error));
+                response
+            }
+        }
+    }
+}
+
+fn status_text(status_code: u16) -> &'static str {
+    match status_code {
+        200 => "OK",
+        201 => "CREATED",
+        202 => "ACCEPTED",
+        204 => "NO CONTENT",
+        301 => "MOVED PERMANENTLY",
+        302 => "FOUND",
+        303 => "SEE OTHER",
+        304 => "NOT MODIFIED",
+        307 => "TEMPORARY REDIRECT",
+        308 => "PERMANENT REDIRECT",
+        400 => "BAD REQUEST",
+        401 => "UNAUTHORIZED",
+        403 => "FORBIDDEN",
+        404 => "NOT FOUND",
+        405 => "METHOD NOT ALLOWED",
+        406 => "NOT ACCEPTABLE",
+        409 => "CONFLICT",
+        410 => "GONE",
+        412 => "PRECONDITION FAILED",
+        413 => "PAYLOAD TOO LARGE",
+        415 => "UNSUPPORTED MEDIA TYPE",
+        416 => "RANGE NOT SATISFIABLE",
+        417 => "EXPECTATION FAILED",
+        422 => "UNPROCESSABLE ENTITY",
+        429 => "TOO MANY REQUESTS",
+        431 => "REQUEST HEADER FIELDS TOO LARGE",
+        451 => "UNAVAILABLE FOR LEGAL REASONS",
+        500 => "INTERNAL SERVER ERROR",
+        501 => "NOT IMPLEMENTED",
+        502 => "BAD GATEWAY",
+        503 => "SERVICE UNAVAILABLE",
+        504 => "GATEWAY TIMEOUT",
+        // Clients act on the code, so any other gets its class's name
+        _ => match status_code / 100 {
+            1 => "INFORMATIONAL",
+            2 => "SUCCESS",
+            3 => "REDIRECTION",
+            4 => "CLIENT ERROR",
+            5 => "SERVER ERROR",
+            _ => "UNKNOWN",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_sets_plain_content_type_and_body() {
+        let response = HttpResponse::text("hello".to_string());
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.header("Content-Type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[test]
+    fn created_sets_status_and_location() {
+        let response = HttpResponse::created("/api/users/3").with_body("{}".to_string());
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.status_text, "CREATED");
+        assert_eq!(response.header("Location"), Some("/api/users/3"));
+    }
+
+    #[test]
+    fn text_content_types_carry_a_charset() {
+        assert_eq!(
+            HttpResponse::ok().header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            HttpResponse::json(200, "OK").header("Content-Type"),
+            Some("application/json; charset=utf-8")
+        );
+        let response = HttpResponse::text("é".to_string()).with_charset("iso-8859-1");
+        assert_eq!(response.header("Content-Type"), Some("text/plain; charset=iso-8859-1"));
+    }
+
+    #[test]
+    fn hsts_header_is_formatted() {
+        let response = HttpResponse::ok().with_hsts(31536000, false, false);
+        assert_eq!(response.header("Strict-Transport-Security"), Some("max-age=31536000"));
+        let response = HttpResponse::ok().with_hsts(63072000, true, true);
+        assert_eq!(
+            response.header("Strict-Transport-Security"),
+            Some("max-age=63072000; includeSubDomains; preload")
+        );
+    }
+
+    #[test]
+    fn json_error_has_the_error_shape() {
+        let response = HttpResponse::json_error(404, "no \"such\" user");
+        assert_eq!(response.status_code, 404);
+        assert_eq!(response.status_text, "NOT FOUND");
+        assert_eq!(response.header("Content-Type"), Some("application/json; charset=utf-8"));
+        assert_eq!(response.body, br#"{"error": "no \"such\" user", "status": 404}"#);
+    }
+
+    // The body of a chunked message, with the framing taken off
+    fn dechunk(mut body: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        loop {
+            let line_end = body.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = usize::from_str_radix(std::str::from_utf8(&body[..line_end]).unwrap(), 16)
+                .unwrap();
+            body = &body[line_end + 2..];
+            if size == 0 {
+                assert_eq!(body, b"\r\n");
+                return data;
+            }
+            data.extend_from_slice(&body[..size]);
+            assert_eq!(&body[size..size + 2], b"\r\n");
+            body = &body[size + 2..];
+        }
+    }
+
+    fn written(response: &HttpResponse) -> Vec<u8> {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn chunks_from_an_iterator_reassemble() {
+        let pieces = vec![b"one ".to_vec(), Vec::new(), b"two ".to_vec(), b"three".to_vec()];
+        let response = HttpResponse::from_chunks(pieces);
+        let out = written(&response);
+
+        let head_end = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&out[..head_end]).to_string();
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+        assert_eq!(dechunk(&out[head_end..]), b"one two three");
+        // Only the first write drains the iterator
+        let again = written(&response);
+        assert!(!again.windows(5).any(|w| w == b"three"));
+    }
+
+    #[test]
+    fn into_response_impls_pick_status_and_body() {
+        let response = "plain".into_response();
+        assert_eq!((response.status_code, response.body.as_slice()), (200, &b"plain"[..]));
+        assert_eq!(response.header("Content-Type"), Some("text/plain; charset=utf-8"));
+
+        let response = String::from("owned").into_response();
+        assert_eq!((response.status_code, response.body.as_slice()), (200, &b"owned"[..]));
+
+        let response = vec![0u8, 1, 2].into_response();
+        assert_eq!(response.header("Content-Type"), Some("application/octet-stream"));
+        assert_eq!(response.body, [0, 1, 2]);
+
+        let response = (409, "taken".to_string()).into_response();
+        assert_eq!((response.status_code, response.status_text.as_str()), (409, "CONFLICT"));
+        assert_eq!(response.body, b"taken");
+
+        let response = HttpResponse::not_found().into_response();
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    fn results_map_errors_to_a_500() {
+        let ok: Result<&str, String> = Ok("fine");
+        assert_eq!(ok.into_response().status_code, 200);
+
+        let err: Result<&str, String> = Err("broken".to_string());
+        let response = err.into_response();
+        assert_eq!(response.status_code, 500);
+        let error = response.error.unwrap();
+        assert_eq!(error.downcast_ref::<String>().map(String::as_str), Some("broken"));
+    }
+
+    #[test]
+    fn no_cache_sets_the_cache_headers() {
+        let response = HttpResponse::ok().with_no_cache();
+        assert_eq!(response.header("Cache-Control"), Some("no-store"));
+        assert_eq!(response.header("Pragma"), Some("no-cache"));
+
+        let response = HttpResponse::ok().with_cache_for(Duration::from_secs(300));
+        assert_eq!(response.header("Cache-Control"), Some("public, max-age=300"));
+    }
+
+    #[test]
+    fn html_sets_the_content_type() {
+        let response = HttpResponse::html("<p>hi</p>".to_string());
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.header("Content-Type"), Some("text/html; charset=utf-8"));
+        assert_eq!(response.body, b"<p>hi</p>");
+    }
+
+    #[test]
+    fn clones_serialize_identically() {
+        let response = HttpResponse::json(201, "CREATED")
+            .with_header("X-Request-Id", "42")
+            .with_body(r#"{"id": 1}"#.to_string());
+        let copy = response.clone();
+        assert_eq!(copy.to_bytes(), response.to_bytes());
+        assert_eq!(written(&copy), written(&response));
+    }
+
+    #[test]
+    fn streamed_clones_share_one_body() {
+        let response = HttpResponse::from_chunks(vec![b"once".to_vec()]);
+        let copy = response.clone();
+        assert!(copy.is_streaming());
+        let out = written(&response);
+        let head_end = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert_eq!(dechunk(&out[head_end..]), b"once");
+        // The first copy written consumed the chunks
+        assert!(!written(&copy).windows(4).any(|w| w == b"once"));
+    }
+
+    #[test]
+    fn map_body_transforms_the_body_and_its_length() {
+        let response = HttpResponse::text("hello".to_string())
+            .map_body(|body| body.to_ascii_uppercase())
+            .map_body(|mut body| {
+                body.extend_from_slice(b", WORLD");
+                body
+            });
+        assert_eq!(response.body, b"HELLO, WORLD");
+        let head = String::from_utf8(response.to_bytes()).unwrap();
+        assert_eq!(head.matches("Content-Length").count(), 1);
+        assert!(head.contains("Content-Length: 12\r\n"));
+    }
+
+    #[test]
+    fn content_disposition_quotes_and_escapes_filenames() {
+        assert_eq!(content_disposition("Q3 report.csv"), "attachment; filename=\"Q3 report.csv\"");
+        assert_eq!(
+            content_disposition("say \"hi\"\\.txt"),
+            "attachment; filename=\"say \\\"hi\\\"\\\\.txt\""
+        );
+        assert_eq!(
+            content_disposition("résumé.pdf"),
+            "attachment; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"
+        );
+    }
+
+    #[test]
+    fn download_streams_the_file_with_its_length() {
+        let path = std::env::temp_dir().join(format!("mtws-download-{}.csv", std::process::id()));
+        std::fs::write(&path, "a,b\n1,2\n").unwrap();
+        let response = HttpResponse::download(&path, "Q3 report.csv").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.header("Content-Disposition"), Some("attachment; filename=\"Q3 report.csv\""));
+        assert_eq!(response.header("Content-Length"), Some("8"));
+        assert_eq!(response.header("Transfer-Encoding"), None);
+        let out = written(&response);
+        assert!(out.ends_with(b"\r\n\r\na,b\n1,2\n"));
+    }
+
+    #[test]
+    fn download_of_a_missing_file_is_an_error() {
+        assert!(HttpResponse::download(Path::new("/nonexistent/file.csv"), "file.csv").is_err());
+    }
+
+    #[test]
+    fn legal_blocks_get_451_with_the_authority() {
+        let response = HttpResponse::unavailable_for_legal_reasons()
+            .with_blocked_by("https://authority.example/orders/42")
+            .with_body("<h1>451 - Unavailable For Legal Reasons</h1>".to_string());
+        assert_eq!(response.status_code, 451);
+        assert_eq!(status_text(451), "UNAVAILABLE FOR LEGAL REASONS");
+        let head = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(head.starts_with("HTTP/1.1 451 UNAVAILABLE FOR LEGAL REASONS\r\n"), "{head}");
+        assert_eq!(
+            response.header("Link"),
+            Some("<https://authority.example/orders/42>; rel=\"blocked-by\"")
+        );
+    }
+
+    fn content_length(response: &HttpResponse) -> Option<String> {
+        let bytes = response.to_bytes();
+        let head = String::from_utf8_lossy(&bytes);
+        let mut lengths = head.lines().filter_map(|line| line.strip_prefix("Content-Length: "));
+        let length = lengths.next().map(str::to_string);
+        assert!(lengths.next().is_none(), "more than one Content-Length");
+        length
+    }
+
+    #[test]
+    fn serialized_length_follows_every_body_mutation() {
+        let mut response = HttpResponse::text("first".to_string());
+        assert_eq!(content_length(&response).as_deref(), Some("5"));
+
+        response.body.extend_from_slice(b" and more");
+        assert_eq!(content_length(&response).as_deref(), Some("14"));
+        response = response.with_header("Content-Length", "999");
+        assert_eq!(content_length(&response).as_deref(), Some("14"));
+        response.body.truncate(2);
+        assert_eq!(content_length(&response).as_deref(), Some("2"));
+        response.body.clear();
+        assert_eq!(content_length(&response).as_deref(), Some("0"));
+        response = response.with_body("replaced".to_string()).map_body(|body| body.repeat(2));
+        assert_eq!(content_length(&response).as_deref(), Some("16"));
+        assert!(response.to_bytes().ends_with(b"\r\n\r\nreplacedreplaced"));
+    }
+
+    #[test]
+    fn head_responses_keep_the_length_without_the_body() {
+        let mut response = HttpResponse::text("twelve bytes".to_string());
+        response.clear_body();
+        assert!(response.no_body);
+        assert_eq!(content_length(&response).as_deref(), Some("12"));
+        assert!(response.to_bytes().ends_with(b"\r\n\r\n"));
+
+        // An empty GET body still announces its length of 0
+        let mut empty = HttpResponse::ok();
+        empty.clear_body();
+        assert_eq!(content_length(&empty).as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn bodiless_statuses_keep_their_headers() {
+        let not_modified = HttpResponse::not_modified().with_header("Content-Length", "7");
+        assert!(not_modified.no_body);
+        assert_eq!(content_length(&not_modified).as_deref(), Some("7"));
+        assert_eq!(content_length(&HttpResponse::not_modified()), None);
+        assert_eq!(content_length(&HttpResponse::new(204, "NO CONTENT")), None);
+
+        let mut head = HttpResponse::text("ignored".to_string());
+        head.no_body = true;
+        assert!(head.to_bytes().ends_with(b"\r\n\r\n"));
+    }
+
+    #[test]
+    fn prebuilt_responses_take_their_status_from_the_bytes() {
+        let bytes = Arc::new(HttpResponse::not_found().with_body("gone".to_string()).to_bytes());
+        let response = HttpResponse::from_prebuilt(Arc::clone(&bytes));
+        assert_eq!((response.status_code, response.status_text.as_str()), (404, "NOT FOUND"));
+        assert_eq!(response.to_bytes(), *bytes);
+        assert_eq!(written(&response), *bytes);
+    }
+
+    #[test]
+    fn status_tuples_get_a_fitting_reason_phrase() {
+        let status_line = |code: u16| {
+            let bytes = (code, String::new()).into_response().to_bytes();
+            String::from_utf8_lossy(&bytes).lines().next().unwrap().to_string()
+        };
+        assert_eq!(status_line(202), "HTTP/1.1 202 ACCEPTED");
+        assert_eq!(status_line(302), "HTTP/1.1 302 FOUND");
+        assert_eq!(status_line(307), "HTTP/1.1 307 TEMPORARY REDIRECT");
+        assert_eq!(status_line(410), "HTTP/1.1 410 GONE");
+        // Codes outside the table are named by their class, never "ERROR"
+        assert_eq!(status_line(207), "HTTP/1.1 207 SUCCESS");
+        assert_eq!(status_line(399), "HTTP/1.1 399 REDIRECTION");
+        assert_eq!(status_line(418), "HTTP/1.1 418 CLIENT ERROR");
+        assert_eq!(status_line(599), "HTTP/1.1 599 SERVER ERROR");
+    }
+
+    #[test]
+    fn head_keeps_a_length_set_on_an_empty_body() {
+        let mut response = HttpResponse::ok().with_header("Content-Length", "4096");
+        response.clear_body();
+        assert_eq!(content_length(&response).as_deref(), Some("4096"));
+        assert!(response.to_bytes().ends_with(b"\r\n\r\n"));
     }
 }
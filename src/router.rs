@@ -1,73 +1,1288 @@
 #![allow(dead_code)]
-use crate::http::{HttpRequest, HttpMethod};
-use crate::response::HttpResponse;
+use crate::http::{HttpRequest, HttpMethod, ParamError};
+use crate::cache::ResponseCache;
+use crate::metrics::Metrics;
+use crate::proxy::{self, Upstream};
+use crate::response::{HttpResponse, IntoResponse};
+use crate::static_files;
+use crate::template;
+use std::any::Any;
+use std::cell::RefCell;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const ROUTE_CACHE_CAPACITY: usize = 256;
 
 type Handler = Box<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+type Params = HashMap<String, String>;
+// Gets the body as a reader instead of in request.body
+type StreamHandler = Box<dyn Fn(&HttpRequest, &mut dyn Read) -> HttpResponse + Send + Sync>;
+
+// The rest of the chain: later middleware followed by the matched handler
+pub type Next<'a> = &'a dyn Fn(&mut HttpRequest) -> HttpResponse;
+type Middleware = Box<dyn Fn(&mut HttpRequest, Next) -> HttpResponse + Send + Sync>;
+type Rewrite = Box<dyn Fn(&mut String) + Send + Sync>;
+// Applied to the finished response, before a HEAD request's body is dropped
+pub(crate) type Finish<'a> = &'a dyn Fn(&HttpRequest, HttpResponse) -> HttpResponse;
+// Maps a handler error to a response, or None if the error is of another type
+type ErrorHandler =
+    Box<dyn Fn(&(dyn Any + Send + Sync), &HttpRequest) -> Option<HttpResponse> + Send + Sync>;
 
 pub struct Router {
     routes: HashMap<(HttpMethod, String), Handler>,
+    streams: HashMap<(HttpMethod, String), StreamHandler>,
+    // Routes with `:name` segments, tried in registration order after exact routes
+    templates: Vec<(HttpMethod, String, Handler)>,
+    hosts: Vec<(String, Router)>,
+    // Handlers for everything under a path prefix, longest prefix first, with
+    // the methods each answers, or None for every method
+    prefixes: Vec<(String, Option<&'static [HttpMethod]>, Handler)>,
+    middleware: Vec<Middleware>,
+    rewrites: Vec<Rewrite>,
+    default_headers: Vec<(String, String)>,
+    // HTML for error responses, keyed by status code or class digit ("4xx" is 4)
+    error_pages: Vec<(ErrorPage, String)>,
+    method_override: bool,
+    // What an override may turn a POST into, and paths where it's ignored
+    override_methods: Vec<HttpMethod>,
+    override_exempt: Vec<String>,
+    error_handlers: Vec<ErrorHandler>,
+    // Directories behind static_dir, checked by check()
+    static_roots: Vec<PathBuf>,
+    trace: bool,
+    connect: Option<Handler>,
+    // Answers GET and HEAD requests no route matched
+    fallback: Option<Handler>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Router {
     pub fn new() -> Self {
         Router {
             routes: HashMap::new(),
+            streams: HashMap::new(),
+            templates: Vec::new(),
+            hosts: Vec::new(),
+            prefixes: Vec::new(),
+            middleware: Vec::new(),
+            rewrites: Vec::new(),
+            default_headers: Vec::new(),
+            error_pages: Vec::new(),
+            method_override: false,
+            override_methods: vec![HttpMethod::PUT, HttpMethod::PATCH, HttpMethod::DELETE],
+            override_exempt: Vec::new(),
+            error_handlers: Vec::new(),
+            static_roots: Vec::new(),
+            trace: false,
+            connect: None,
+            fallback: None,
+            metrics: None,
         }
     }
 
-    pub fn get<F>(mut self, path: &str, handler: F) -> Self 
-    where 
-        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    // Middleware runs in registration order, the first registered being the outermost
+    pub fn middleware<F>(mut self, middleware: F) -> Self
+    where
+        F: Fn(&mut HttpRequest, Next) -> HttpResponse + Send + Sync + 'static,
     {
-        self.routes.insert(
-            (HttpMethod::GET, path.to_string()), 
-            Box::new(handler)
-        );
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    // Routes in `router` only answer requests whose Host header matches `pattern`,
+    // either exactly ("api.example.com") or as a wildcard subdomain ("*.example.com").
+    // Requests a host router has no route for fall through to this router's routes.
+    // Only the routes of `router` are used, not its middleware or default headers.
+    pub fn host(mut self, pattern: &str, router: Router) -> Self {
+        self.hosts.push((pattern.to_ascii_lowercase(), router));
         self
     }
 
-    pub fn post<F>(mut self, path: &str, handler: F) -> Self 
-    where 
-        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    // Added to every response that doesn't already set the header itself
+    pub fn default_header(mut self, key: &str, value: &str) -> Self {
+        self.default_headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // Renders HTML error responses for `status`, either one code such as "404"
+    // or a class such as "5xx", from `template` with {{status}} and {{message}}
+    // filled in. A code's page wins over its class's. Responses of other types,
+    // such as JSON errors from an API, are left alone. Panics on any other
+    // `status`.
+    pub fn error_page(mut self, status: &str, template: &str) -> Self {
+        let page = match status.as_bytes() {
+            [class @ b'4'..=b'5', b'x', b'x'] => ErrorPage::Class((class - b'0') as u16),
+            _ => match status.parse() {
+                Ok(code @ 400..=599) => ErrorPage::Status(code),
+                _ => panic!("error page status must be a 4xx or 5xx code or class, not {status}"),
+            },
+        };
+        self.error_pages.retain(|(existing, _)| *existing != page);
+        self.error_pages.push((page, template.to_string()));
+        self
+    }
+
+    // Changes the decoded request path before middleware and route matching,
+    // e.g. to strip the base path the app is mounted under. Rewrites run in the
+    // order added; raw_target keeps the path as sent. Those of host-specific
+    // routers aren't applied.
+    pub fn rewrite<F>(mut self, rewrite: F) -> Self
+    where
+        F: Fn(&mut String) + Send + Sync + 'static,
     {
-        self.routes.insert(
-            (HttpMethod::POST, path.to_string()), 
-            Box::new(handler)
-        );
+        self.rewrites.push(Box::new(rewrite));
+        self
+    }
+
+    // Lets POST requests be routed as another method via the X-HTTP-Method-Override
+    // header or a `_method` form field. Off by default since it widens what a
+    // plain form post can reach. Only POST is ever overridden, only to a
+    // method from override_methods (PUT, PATCH and DELETE by default), and
+    // never on a path passed to no_method_override; otherwise the request
+    // stays a POST.
+    pub fn method_override(mut self, enabled: bool) -> Self {
+        self.method_override = enabled;
+        self
+    }
+
+    pub fn override_methods(mut self, methods: &[HttpMethod]) -> Self {
+        self.override_methods = methods.to_vec();
+        self
+    }
+
+    // Keeps POSTs to `path` as POSTs whatever override they carry. `path` may
+    // be a template such as "/admin/:id", matched like a route's.
+    pub fn no_method_override(mut self, path: &str) -> Self {
+        self.override_exempt.push(path.to_string());
+        self
+    }
+
+    // Turns `Err(E)` returned by any handler into a response in one place. An
+    // error without a handler for its type gets a plain 500.
+    pub fn error_handler<E, F>(mut self, handler: F) -> Self
+    where
+        E: Send + Sync + 'static,
+        F: Fn(&E, &HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.error_handlers.push(Box::new(move |error, request| {
+            error.downcast_ref::<E>().map(|error| handler(error, request))
+        }));
+        self
+    }
+
+    // TRACE echoes the request back, which can expose headers to scripts
+    // (cross-site tracing), so it gets a 405 unless enabled here
+    pub fn allow_trace(mut self, enabled: bool) -> Self {
+        self.trace = enabled;
+        self
+    }
+
+    // Handles CONNECT requests, whose target is an authority such as
+    // "example.com:443" rather than a path. Without one CONNECT gets a 405.
+    pub fn connect<F, R>(mut self, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.connect = Some(Box::new(move |request| handler(request).into_response()));
         self
     }
 
-    pub fn put<F>(mut self, path: &str, handler: F) -> Self 
-    where 
-        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    pub fn get<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::GET, path, handler)
+    }
+
+    // Like get, but only registers the route when `enabled`, for features one
+    // binary turns on per deployment: `.get_if(config.admin, "/admin", admin)`
+    pub fn get_if<F, R>(self, enabled: bool, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.when(enabled, |router| router.get(path, handler))
+    }
+
+    // Applies `register` only when `enabled`, to add a group of routes or
+    // middleware together: `.when(admin, |r| r.get(...).post(...))`
+    pub fn when<F>(self, enabled: bool, register: F) -> Self
+    where
+        F: FnOnce(Router) -> Router,
+    {
+        if enabled { register(self) } else { self }
+    }
+
+    // Serves repeated requests for the same path and query from memory for `ttl`
+    // without calling the handler. Only for idempotent, non-personalized responses.
+    pub fn get_cached<F, R>(self, path: &str, ttl: Duration, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let cache = ResponseCache::new(ttl, ROUTE_CACHE_CAPACITY);
+        self.get(path, move |request| {
+            let key = format!("{} {}", request.method, request.raw_target);
+            cache.get_or_insert_with(&key, || handler(request).into_response())
+        })
+    }
+
+    // A GET route also answers HEAD requests for the same path unless a HEAD
+    // route is registered for it, which always takes precedence
+    pub fn head<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::HEAD, path, handler)
+    }
+
+    pub fn post<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.routes.insert(
-            (HttpMethod::PUT, path.to_string()), 
-            Box::new(handler)
+        self.route(HttpMethod::POST, path, handler)
+    }
+
+    pub fn put<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::PUT, path, handler)
+    }
+
+    // For partial updates, where the body carries only the fields to change
+    pub fn patch<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::PATCH, path, handler)
+    }
+
+    pub fn delete<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::DELETE, path, handler)
+    }
+
+    // Replaces the automatic OPTIONS answer for `path`
+    pub fn options<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::OPTIONS, path, handler)
+    }
+
+    // Like post, but the handler reads the body from the connection itself, so
+    // a large upload can go to disk without being held in memory. The reader
+    // ends with the body; whatever the handler leaves unread is discarded. The
+    // body is passed on as sent, Content-Encoding and all.
+    pub fn post_stream<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, &mut dyn Read) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.stream_route(HttpMethod::POST, path, handler)
+    }
+
+    pub fn put_stream<F, R>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, &mut dyn Read) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.stream_route(HttpMethod::PUT, path, handler)
+    }
+
+    // Splits requests for one method and path between several handlers in
+    // proportion to their weights, for A/B tests and canaries. Panics if
+    // `variants` has no handler with a non-zero weight.
+    pub fn weighted(self, method: HttpMethod, path: &str, variants: Weighted) -> Self {
+        assert!(
+            variants.variants.iter().any(|(weight, _)| *weight > 0),
+            "weighted route needs a handler with non-zero weight"
         );
+        self.route(method, path, move |request| variants.pick()(request))
+    }
+
+    // Forwards requests for `prefix` and everything below it, whatever the method,
+    // to `upstream` ("http://host:port/base") with the prefix stripped. Exact
+    // routes still take precedence. Panics if `upstream` isn't an http:// URL.
+    pub fn proxy(mut self, prefix: &str, upstream: &str) -> Self {
+        let upstream = Upstream::parse(upstream).expect("proxy upstream must be an http:// URL");
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let strip = prefix.clone();
+        self.prefixes.push((
+            prefix,
+            None,
+            Box::new(move |request| proxy::forward(request, &strip, &upstream)),
+        ));
+        self.prefixes.sort_by_key(|(prefix, _, _)| Reverse(prefix.len()));
+        self
+    }
+
+    // Serves files below `root` for GET and HEAD requests under `prefix`, so
+    // "/assets/app.css" maps to "<root>/app.css" for the prefix "/assets".
+    // Other methods under `prefix` get a 405, and OPTIONS the 204 saying so.
+    pub fn static_dir(mut self, prefix: &str, root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let strip = prefix.clone();
+        self.static_roots.push(root.clone());
+        self.prefixes.push((
+            prefix,
+            Some(&[HttpMethod::GET, HttpMethod::HEAD]),
+            Box::new(move |request| {
+                let relative = request.path.strip_prefix(strip.as_str()).unwrap_or("");
+                static_files::serve_dir(request, &root, relative)
+            }),
+        ));
+        self.prefixes.sort_by_key(|(prefix, _, _)| Reverse(prefix.len()));
+        self
+    }
+
+    // Counts the responses the server sends while this router is in use, and
+    // the connections open on it, serving them as Prometheus-style text at `path`
+    pub fn metrics(mut self, path: &str) -> Self {
+        let metrics = Arc::new(Metrics::default());
+        self.metrics = Some(Arc::clone(&metrics));
+        self.get(path, move |_request| {
+            HttpResponse::ok()
+                .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .with_no_cache()
+                .with_body(metrics.render())
+        })
+    }
+
+    // The counters behind the metrics route, if there is one
+    pub fn metrics_handle(&self) -> Option<&Metrics> {
+        self.metrics.as_deref()
+    }
+
+    // Serves GET and HEAD requests that no route matches from `root`, so API
+    // routes take precedence over files. With `spa` set, a path that doesn't
+    // look like a file ("/settings/profile", not "/app.js") gets root's
+    // index.html, for single-page apps that route in the browser.
+    pub fn fallback_static(mut self, root: impl AsRef<Path>, spa: bool) -> Self {
+        let root = root.as_ref().to_path_buf();
+        self.static_roots.push(root.clone());
+        self.fallback = Some(Box::new(move |request| {
+            let response = static_files::serve_dir(request, &root, &request.path);
+            let looks_like_file = request
+                .path
+                .rsplit('/')
+                .next()
+                .is_some_and(|name| name.contains('.'));
+            if spa && response.status_code == 404 && !looks_like_file {
+                return static_files::serve_file(request, &root.join("index.html"));
+            }
+            response
+        }));
+        self
+    }
+
+    // Verifies setup that can only fail at runtime, such as a static_dir root
+    // that doesn't exist. Server::run calls this before accepting connections.
+    pub fn check(&self) -> io::Result<()> {
+        for root in &self.static_roots {
+            static_files::check_root(root)?;
+        }
+        for (_, router) in &self.hosts {
+            router.check()?;
+        }
+        Ok(())
+    }
+
+    // A path segment written as `:name` matches any one segment, which the
+    // handler gets back from request.param("name"). Trailing segments written
+    // `:name?` may also be left off, so "/items/:id?" matches "/items" (where
+    // param("id") is None) as well as "/items/5". Exact routes win over
+    // templates, and templates are tried in the order they were added. Panics
+    // if an optional segment is followed by a required one.
+    fn route<F, R>(mut self, method: HttpMethod, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let handler: Handler = Box::new(move |request| handler(request).into_response());
+        if path.split('/').any(|segment| segment.starts_with(':')) {
+            let mut segments = path.split('/').skip_while(|segment| !is_optional(segment));
+            assert!(
+                segments.all(is_optional),
+                "optional segments must come last in route {path}"
+            );
+            self.templates.retain(|(m, p, _)| !(*m == method && p == path));
+            self.templates.push((method, path.to_string(), handler));
+        } else {
+            self.routes.insert((method, path.to_string()), handler);
+        }
         self
     }
 
-    pub fn delete<F>(mut self, path: &str, handler: F) -> Self 
-    where 
-        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    fn stream_route<F, R>(mut self, method: HttpMethod, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, &mut dyn Read) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.routes.insert(
-            (HttpMethod::DELETE, path.to_string()), 
-            Box::new(handler)
+        self.streams.insert(
+            (method, path.to_string()),
+            Box::new(move |request, body| handler(request, body).into_response()),
         );
         self
     }
 
-    pub fn handle(&self, request: &HttpRequest) -> HttpResponse {
-        let key = (request.method.clone(), request.path.clone());
-        
-        if let Some(handler) = self.routes.get(&key) {
-            handler(request)
+    // Registered method/path pairs sorted by path, with path templates as written.
+    // Routes of host-specific routers are not included.
+    pub fn routes(&self) -> impl Iterator<Item = (HttpMethod, &str)> {
+        let mut routes: Vec<(HttpMethod, &str)> = self
+            .routes
+            .keys()
+            .chain(self.streams.keys())
+            .map(|(method, path)| (method.clone(), path.as_str()))
+            .chain(
+                self.templates
+                    .iter()
+                    .map(|(method, path, _)| (method.clone(), path.as_str())),
+            )
+            .collect();
+        routes.sort_by(|a, b| (a.1, a.0.as_str()).cmp(&(b.1, b.0.as_str())));
+        routes.into_iter()
+    }
+
+    pub fn handle(&self, request: &mut HttpRequest) -> HttpResponse {
+        self.handle_then(request, &|_, response| response)
+    }
+
+    // Like handle, with `finish` applied while the response still has its
+    // body, so a change it makes, such as compression, shows in the headers
+    // of a HEAD response just as it does for GET
+    pub(crate) fn handle_then(&self, request: &mut HttpRequest, finish: Finish) -> HttpResponse {
+        self.handle_with(request, &|request| self.dispatch(request), finish)
+    }
+
+    // Whether the request goes to a post_stream or put_stream route, in which
+    // case the server leaves its body unread and calls handle_streaming
+    pub fn streams_body(&self, request: &HttpRequest) -> bool {
+        // Asked before handling, so match on the path as it will be rewritten
+        let mut path = request.path.clone();
+        for rewrite in &self.rewrites {
+            rewrite(&mut path);
+        }
+        self.find_stream_handler(request, &request.method, &path).is_some()
+    }
+
+    // Like handle, with the body read from `body` by a streaming route
+    pub fn handle_streaming(&self, request: &mut HttpRequest, body: &mut dyn Read) -> HttpResponse {
+        self.handle_streaming_then(request, body, &|_, response| response)
+    }
+
+    pub(crate) fn handle_streaming_then(
+        &self,
+        request: &mut HttpRequest,
+        body: &mut dyn Read,
+        finish: Finish,
+    ) -> HttpResponse {
+        let body = RefCell::new(body);
+        let endpoint = |request: &mut HttpRequest| {
+            match self.find_stream_handler(request, &request.method, &request.path) {
+                Some(handler) => self.map_error(handler(request, *body.borrow_mut()), request),
+                None => self.dispatch(request),
+            }
+        };
+        self.handle_with(request, &endpoint, finish)
+    }
+
+    fn handle_with(&self, request: &mut HttpRequest, endpoint: Next, finish: Finish) -> HttpResponse {
+        for rewrite in &self.rewrites {
+            rewrite(&mut request.path);
+        }
+        if self.method_override
+            && request.method == HttpMethod::POST
+            && !self
+                .override_exempt
+                .iter()
+                .any(|exempt| match_template(exempt, &request.path).is_some())
+            && let Some(method) = override_method(request)
+            && self.override_methods.contains(&method)
+        {
+            request.method = method;
+        }
+
+        let is_head = request.method == HttpMethod::HEAD;
+        let response = self.run_middleware(0, request, endpoint);
+        let mut response = finish(request, self.apply_default_headers(response));
+        if is_head {
+            response.clear_body();
+        }
+        response
+    }
+
+    // Finishes a response the router or server produced: the error page for
+    // its status, if any, then the default headers
+    pub fn apply_default_headers(&self, response: HttpResponse) -> HttpResponse {
+        let mut response = self.apply_error_page(response);
+        for (key, value) in &self.default_headers {
+            if !response.has_header(key) {
+                response = response.with_header(key, value);
+            }
+        }
+        response
+    }
+
+    fn run_middleware(&self, index: usize, request: &mut HttpRequest, endpoint: Next) -> HttpResponse {
+        match self.middleware.get(index) {
+            Some(middleware) => middleware(request, &|request| {
+                self.run_middleware(index + 1, request, endpoint)
+            }),
+            None => endpoint(request),
+        }
+    }
+
+    fn dispatch(&self, request: &mut HttpRequest) -> HttpResponse {
+        let disabled = || {
+            HttpResponse::method_not_allowed()
+                .with_body("<h1>405 - Method Not Allowed</h1>".to_string())
+        };
+        match request.method {
+            HttpMethod::TRACE if self.trace => return trace_echo(request),
+            HttpMethod::TRACE => return disabled(),
+            HttpMethod::CONNECT => {
+                return match &self.connect {
+                    Some(handler) => self.map_error(handler(request), request),
+                    None => disabled(),
+                };
+            }
+            _ => {}
+        }
+
+        let handler = self.find_handler(request, &request.method).or_else(|| {
+            if request.method == HttpMethod::HEAD {
+                self.find_handler(request, &HttpMethod::GET)
+            } else {
+                None
+            }
+        });
+
+        match handler {
+            Some((handler, params)) => {
+                request.params = params;
+                self.map_error(handler(request), request)
+            }
+            None if request.method == HttpMethod::OPTIONS => self.options_response(request),
+            None if matches!(request.method, HttpMethod::GET | HttpMethod::HEAD)
+                && let Some(fallback) = &self.fallback =>
+            {
+                fallback(request)
+            }
+            None if let Some((_, Some(methods), _)) = self.find_prefix(&request.path) => {
+                HttpResponse::method_not_allowed()
+                    .with_header("Allow", &self.allow_header(methods))
+                    .with_body("<h1>405 - Method Not Allowed</h1>".to_string())
+            }
+            None => HttpResponse::not_found()
+                .with_body("<h1>404 - Page Not Found</h1>".to_string()),
+        }
+    }
+
+    // A 204 whose Allow header lists the methods routed for the request's path,
+    // or for `OPTIONS *` those routed anywhere. A 404 if there are none.
+    fn options_response(&self, request: &HttpRequest) -> HttpResponse {
+        let allowed = if request.path == "*" {
+            let mut methods: Vec<HttpMethod> = self.routes().map(|(method, _)| method).collect();
+            if self.fallback.is_some() {
+                methods.push(HttpMethod::GET);
+            }
+            methods
         } else {
-            HttpResponse::not_found()
-                .with_body("<h1>404 - Page Not Found</h1>".to_string())
+            ALLOW_ORDER
+                .iter()
+                .filter(|method| {
+                    self.find_handler(request, method).is_some()
+                        || self.find_stream_handler(request, method, &request.path).is_some()
+                        || (**method == HttpMethod::GET && self.fallback.is_some())
+                })
+                .cloned()
+                .collect()
+        };
+        if allowed.is_empty() {
+            return HttpResponse::not_found()
+                .with_body("<h1>404 - Page Not Found</h1>".to_string());
+        }
+
+        let mut response = HttpResponse::new(204, "NO CONTENT").with_header("Allow", &self.allow_header(&allowed));
+        response.headers.remove("Content-Type");
+        response
+    }
+
+    // The Allow value for a path routed for `allowed`: HEAD is answered
+    // wherever GET is, and OPTIONS everywhere
+    fn allow_header(&self, allowed: &[HttpMethod]) -> String {
+        let allow: Vec<&str> = ALLOW_ORDER
+            .iter()
+            .filter(|method| {
+                allowed.contains(method)
+                    || (**method == HttpMethod::HEAD && allowed.contains(&HttpMethod::GET))
+                    || **method == HttpMethod::OPTIONS
+                    || (**method == HttpMethod::TRACE && self.trace)
+            })
+            .map(HttpMethod::as_str)
+            .collect();
+        allow.join(", ")
+    }
+
+    fn apply_error_page(&self, response: HttpResponse) -> HttpResponse {
+        let is_html = response
+            .header("Content-Type")
+            .is_some_and(|value| value.starts_with("text/html"));
+        if !is_html || response.is_streaming() {
+            return response;
         }
+        let code = response.status_code;
+        let page = self
+            .error_pages
+            .iter()
+            .find(|(page, _)| *page == ErrorPage::Status(code))
+            .or_else(|| {
+                self.error_pages
+                    .iter()
+                    .find(|(page, _)| *page == ErrorPage::Class(code / 100))
+            });
+        let Some((_, page)) = page else {
+            return response;
+        };
+
+        let vars = HashMap::from([
+            ("status", code.to_string()),
+            ("message", response.status_text.clone()),
+        ]);
+        response.with_body(template::render(page, &vars))
+    }
+
+    fn map_error(&self, response: HttpResponse, request: &HttpRequest) -> HttpResponse {
+        let Some(error) = &response.error else {
+            return response;
+        };
+        self.error_handlers
+            .iter()
+            .find_map(|handler| handler(error.as_ref(), request))
+            .or_else(|| {
+                // A path segment that didn't parse is the client's mistake
+                error.downcast_ref::<ParamError>().map(|_| {
+                    HttpResponse::bad_request().with_body("<h1>400 - Bad Request</h1>".to_string())
+                })
+            })
+            .unwrap_or(response)
+    }
+
+    fn find_stream_handler(
+        &self,
+        request: &HttpRequest,
+        method: &HttpMethod,
+        path: &str,
+    ) -> Option<&StreamHandler> {
+        if let Some(host) = request.headers.get("host") {
+            let host = host.split(':').next().unwrap_or("").to_ascii_lowercase();
+            for (pattern, router) in &self.hosts {
+                if host_matches(pattern, &host)
+                    && let Some(handler) = router.find_stream_handler(request, method, path)
+                {
+                    return Some(handler);
+                }
+            }
+        }
+
+        self.streams.get(&(method.clone(), path.to_string()))
+    }
+
+    fn find_handler(
+        &self,
+        request: &HttpRequest,
+        method: &HttpMethod,
+    ) -> Option<(&Handler, Params)> {
+        if let Some(host) = request.headers.get("host") {
+            // Ignore the port and letter case
+            let host = host.split(':').next().unwrap_or("").to_ascii_lowercase();
+            for (pattern, router) in &self.hosts {
+                if host_matches(pattern, &host)
+                    && let Some(handler) = router.find_handler(request, method)
+                {
+                    return Some(handler);
+                }
+            }
+        }
+
+        // The query string doesn't take part in route matching
+        let path = request.path.as_str();
+        if let Some(handler) = self.routes.get(&(method.clone(), path.to_string())) {
+            return Some((handler, Params::new()));
+        }
+        for (template_method, template, handler) in &self.templates {
+            if template_method == method
+                && let Some(params) = match_template(template, path)
+            {
+                return Some((handler, params));
+            }
+        }
+        // The longest prefix owns the path, even for a method it doesn't answer
+        self.find_prefix(path)
+            .filter(|(_, methods, _)| methods.is_none_or(|methods| methods.contains(method)))
+            .map(|(_, _, handler)| (handler, Params::new()))
+    }
+
+    fn find_prefix(&self, path: &str) -> Option<&(String, Option<&'static [HttpMethod]>, Handler)> {
+        self.prefixes.iter().find(|(prefix, _, _)| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+// Handlers sharing one route, picked by smooth weighted round-robin: with
+// weights 3 and 1 every four requests go A, A, B, A, so the split is exact
+// and deterministic rather than random
+#[derive(Default)]
+pub struct Weighted {
+    variants: Vec<(u32, Handler)>,
+    // The running score of each variant; the highest is picked next
+    current: Mutex<Vec<i64>>,
+}
+
+impl Weighted {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn variant<F, R>(mut self, weight: u32, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.variants
+            .push((weight, Box::new(move |request| handler(request).into_response())));
+        self.current.get_mut().unwrap().push(0);
+        self
+    }
+
+    fn pick(&self) -> &Handler {
+        let total: i64 = self.variants.iter().map(|(weight, _)| *weight as i64).sum();
+        let mut current = self.current.lock().unwrap();
+        for (score, (weight, _)) in current.iter_mut().zip(&self.variants) {
+            *score += *weight as i64;
+        }
+        let chosen = (0..current.len()).max_by_key(|&i| (current[i], Reverse(i))).unwrap();
+        current[chosen] -= total;
+        &self.variants[chosen].1
+    }
+}
+
+fn override_method(request: &HttpRequest) -> Option<HttpMethod> {
+    if let Some(method) = request.headers.get("x-http-method-override") {
+        return HttpMethod::from_str(&method.to_uppercase());
+    }
+
+    let is_form = request
+        .headers
+        .get("content-type")
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return None;
+    }
+
+    request
+        .body_string()
+        .ok()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "_method")
+        .and_then(|(_, value)| HttpMethod::from_str(&value.to_uppercase()))
+}
+
+// The request as received, minus credentials, as a message/http body
+fn trace_echo(request: &HttpRequest) -> HttpResponse {
+    let mut echo = format!("{} {} {}\r\n", request.method, request.raw_target, request.version);
+    let mut headers: Vec<_> = request.headers.iter().collect();
+    headers.sort();
+    for (key, value) in headers {
+        if !matches!(key.as_str(), "authorization" | "cookie" | "proxy-authorization") {
+            echo.push_str(&format!("{}: {}\r\n", key, value));
+        }
+    }
+    echo.push_str("\r\n");
+
+    HttpResponse::ok()
+        .with_header("Content-Type", "message/http")
+        .with_body(echo)
+}
+
+// The `:name` captures if `path` fits `template` segment for segment
+fn match_template(template: &str, path: &str) -> Option<Params> {
+    let mut template_segments = template.split('/');
+    let mut path_segments = path.split('/');
+    let mut params = Params::new();
+    loop {
+        match (template_segments.next(), path_segments.next()) {
+            (None, None) => return Some(params),
+            // Once the path runs out, only optional segments may be left over
+            (Some(expected), None) if is_optional(expected) => {}
+            (Some(expected), Some(segment)) => match expected.strip_prefix(':') {
+                Some(name) if !segment.is_empty() => {
+                    params.insert(name.trim_end_matches('?').to_string(), segment.to_string());
+                }
+                None if expected == segment => {}
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+}
+
+// The order methods are listed in an Allow header
+const ALLOW_ORDER: [HttpMethod; 8] = [
+    HttpMethod::GET,
+    HttpMethod::HEAD,
+    HttpMethod::POST,
+    HttpMethod::PUT,
+    HttpMethod::PATCH,
+    HttpMethod::DELETE,
+    HttpMethod::OPTIONS,
+    HttpMethod::TRACE,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorPage {
+    Status(u16),
+    Class(u16),
+}
+
+fn is_optional(segment: &str) -> bool {
+    segment.starts_with(':') && segment.ends_with('?')
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+
+    fn send(router: &Router, request: RequestBuilder) -> HttpResponse {
+        router.handle(&mut request.build())
+    }
+
+    #[test]
+    fn default_headers_fill_in_missing_headers() {
+        let router = Router::new()
+            .default_header("X-Frame-Options", "DENY")
+            .get("/", |_| HttpResponse::ok())
+            .get("/framed", |_| HttpResponse::ok().with_header("X-Frame-Options", "SAMEORIGIN"));
+
+        let response = send(&router, RequestBuilder::get("/"));
+        assert_eq!(response.header("X-Frame-Options"), Some("DENY"));
+        let response = send(&router, RequestBuilder::get("/framed"));
+        assert_eq!(response.header("X-Frame-Options"), Some("SAMEORIGIN"));
+    }
+
+    #[test]
+    fn override_header_routes_a_post_as_delete() {
+        let router = Router::new()
+            .method_override(true)
+            .delete("/api/users/1", |_| HttpResponse::text("deleted".to_string()));
+
+        let request = RequestBuilder::post("/api/users/1").header("X-HTTP-Method-Override", "DELETE");
+        let response = send(&router, request);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, b"deleted");
+
+        let request = RequestBuilder::post("/api/users/1")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("_method=delete");
+        assert_eq!(send(&router, request).body, b"deleted");
+    }
+
+    #[test]
+    fn override_is_off_by_default() {
+        let router = Router::new().delete("/api/users/1", |_| HttpResponse::ok());
+        let request = RequestBuilder::post("/api/users/1").header("X-HTTP-Method-Override", "DELETE");
+        assert_eq!(send(&router, request).status_code, 404);
+    }
+
+    #[test]
+    fn cached_route_calls_the_handler_once_within_the_ttl() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let router = Router::new().get_cached("/report", Duration::from_secs(60), move |_| {
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            HttpResponse::text(n.to_string())
+        });
+
+        for _ in 0..3 {
+            assert_eq!(send(&router, RequestBuilder::get("/report")).body, b"1");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A different query is cached separately
+        assert_eq!(send(&router, RequestBuilder::get("/report?day=2")).body, b"2");
+    }
+
+    #[test]
+    fn host_routers_answer_the_same_path_differently() {
+        let router = Router::new()
+            .host("api.example.com", Router::new().get("/", |_| HttpResponse::text("api".to_string())))
+            .host("*.example.org", Router::new().get("/", |_| HttpResponse::text("org".to_string())))
+            .get("/", |_| HttpResponse::text("main".to_string()));
+
+        let get = |host: &str| send(&router, RequestBuilder::get("/").header("Host", host)).body;
+        assert_eq!(get("api.example.com"), b"api");
+        assert_eq!(get("API.example.com:8080"), b"api");
+        assert_eq!(get("shop.example.org"), b"org");
+        assert_eq!(get("example.org"), b"main");
+        assert_eq!(get("other.test"), b"main");
+    }
+
+    #[test]
+    fn routes_lists_every_registration() {
+        let router = Router::new()
+            .get("/b", |_| HttpResponse::ok())
+            .post("/a", |_| HttpResponse::ok())
+            .get("/a", |_| HttpResponse::ok())
+            .delete("/users/:id", |_| HttpResponse::ok())
+            .put_stream("/upload", |_, _| HttpResponse::ok());
+
+        let routes: Vec<(HttpMethod, &str)> = router.routes().collect();
+        assert_eq!(
+            routes,
+            vec![
+                (HttpMethod::GET, "/a"),
+                (HttpMethod::POST, "/a"),
+                (HttpMethod::GET, "/b"),
+                (HttpMethod::PUT, "/upload"),
+                (HttpMethod::DELETE, "/users/:id"),
+            ]
+        );
+    }
+
+    #[test]
+    fn middleware_can_hand_values_to_handlers() {
+        #[derive(Debug, PartialEq)]
+        struct User(String);
+
+        let router = Router::new()
+            .middleware(|request, next| {
+                let user = request.headers.get("x-user").cloned();
+                match user {
+                    Some(name) => {
+                        request.extensions.insert(User(name));
+                        next(request)
+                    }
+                    None => HttpResponse::new(401, "UNAUTHORIZED"),
+                }
+            })
+            .get("/me", |request| {
+                let user = request.extensions.get::<User>().unwrap();
+                HttpResponse::text(format!("hello {}", user.0))
+            });
+
+        let response = send(&router, RequestBuilder::get("/me").header("X-User", "alice"));
+        assert_eq!(response.body, b"hello alice");
+        assert_eq!(send(&router, RequestBuilder::get("/me")).status_code, 401);
+    }
+
+    #[derive(Debug)]
+    enum ApiError {
+        NotFound,
+        Validation(String),
+    }
+
+    #[test]
+    fn error_handler_maps_a_custom_error_type() {
+        let router = Router::new()
+            .error_handler(|error: &ApiError, _| match error {
+                ApiError::NotFound => HttpResponse::not_found(),
+                ApiError::Validation(message) => HttpResponse::bad_request().with_body(message.clone()),
+            })
+            .get("/missing", |_| Err::<HttpResponse, _>(ApiError::NotFound))
+            .get("/invalid", |_| Err::<HttpResponse, _>(ApiError::Validation("bad id".to_string())))
+            .get("/fine", |_| Ok::<_, ApiError>(HttpResponse::text("fine".to_string())));
+
+        assert_eq!(send(&router, RequestBuilder::get("/missing")).status_code, 404);
+        let response = send(&router, RequestBuilder::get("/invalid"));
+        assert_eq!(response.status_code, 400);
+        assert_eq!(response.body, b"bad id");
+        let response = send(&router, RequestBuilder::get("/fine"));
+        assert_eq!((response.status_code, response.body.as_slice()), (200, &b"fine"[..]));
+    }
+
+    #[test]
+    fn unmapped_errors_are_a_500() {
+        let router = Router::new()
+            .error_handler(|_: &ApiError, _| HttpResponse::bad_request())
+            .get("/io", |_| Err::<HttpResponse, _>(std::io::Error::other("disk")));
+        assert_eq!(send(&router, RequestBuilder::get("/io")).status_code, 500);
+    }
+
+    fn weighted_router() -> Router {
+        let variants = Weighted::new()
+            .variant(3, |_| HttpResponse::text("a".to_string()))
+            .variant(1, |_| HttpResponse::text("b".to_string()));
+        Router::new().weighted(HttpMethod::GET, "/", variants)
+    }
+
+    #[test]
+    fn weighted_routes_interleave_by_weight() {
+        let router = weighted_router();
+        let picks: Vec<_> = (0..8)
+            .map(|_| String::from_utf8(send(&router, RequestBuilder::get("/")).body).unwrap())
+            .collect();
+        assert_eq!(picks.concat(), "aabaaaba");
+    }
+
+    #[test]
+    fn weighted_routes_split_across_threads() {
+        let router = weighted_router();
+        let b_count = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..100)
+                            .filter(|_| send(&router, RequestBuilder::get("/")).body == b"b")
+                            .count()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).sum::<usize>()
+        });
+        assert_eq!(b_count, 200);
+    }
+
+    #[test]
+    #[should_panic(expected = "non-zero weight")]
+    fn weighted_routes_need_a_weight() {
+        let variants = Weighted::new().variant(0, |_| HttpResponse::ok());
+        let _ = Router::new().weighted(HttpMethod::GET, "/", variants);
+    }
+
+    #[test]
+    fn a_json_only_handler_answers_html_requests_with_406() {
+        let router = Router::new().get("/report", |request| match request.preferred(&["application/json"]) {
+            Some(_) => HttpResponse::json(200, "OK").with_body("{}".to_string()),
+            None => HttpResponse::not_acceptable(),
+        });
+        let html = RequestBuilder::get("/report").header("Accept", "text/html");
+        let response = send(&router, html);
+        assert_eq!((response.status_code, response.status_text.as_str()), (406, "NOT ACCEPTABLE"));
+        let json = RequestBuilder::get("/report").header("Accept", "application/*");
+        assert_eq!(send(&router, json).status_code, 200);
+    }
+
+    fn item_router() -> Router {
+        Router::new().get("/items/:id?", |request| {
+            HttpResponse::text(format!("{:?}", request.param("id")))
+        })
+    }
+
+    #[test]
+    fn optional_segments_match_present_and_absent() {
+        let router = item_router();
+        assert_eq!(send(&router, RequestBuilder::get("/items/5")).body, b"Some(\"5\")");
+        assert_eq!(send(&router, RequestBuilder::get("/items")).body, b"None");
+        assert_eq!(send(&router, RequestBuilder::get("/items/5/extra")).status_code, 404);
+    }
+
+    #[test]
+    fn several_optional_segments_can_trail() {
+        let router = Router::new().get("/files/:dir?/:name?", |request| {
+            HttpResponse::text(format!("{:?} {:?}", request.param("dir"), request.param("name")))
+        });
+        assert_eq!(send(&router, RequestBuilder::get("/files")).body, b"None None");
+        assert_eq!(send(&router, RequestBuilder::get("/files/a")).body, b"Some(\"a\") None");
+        assert_eq!(send(&router, RequestBuilder::get("/files/a/b")).body, b"Some(\"a\") Some(\"b\")");
+    }
+
+    #[test]
+    fn exact_routes_win_over_optional_templates() {
+        let router = item_router().get("/items", |_| HttpResponse::text("all items".to_string()));
+        assert_eq!(send(&router, RequestBuilder::get("/items")).body, b"all items");
+        assert_eq!(send(&router, RequestBuilder::get("/items/7")).body, b"Some(\"7\")");
+    }
+
+    #[test]
+    #[should_panic(expected = "optional segments must come last")]
+    fn a_required_segment_after_an_optional_one_panics() {
+        let _ = Router::new().get("/items/:id?/edit", |_| HttpResponse::ok());
+    }
+
+    fn user_router() -> Router {
+        Router::new().get("/api/users/:id?", |request| -> Result<HttpResponse, ParamError> {
+            let id: u64 = request.param_as("id")?;
+            Ok(HttpResponse::text(format!("user {}", id)))
+        })
+    }
+
+    #[test]
+    fn typed_params_parse_or_answer_400() {
+        let router = user_router();
+        let response = send(&router, RequestBuilder::get("/api/users/42"));
+        assert_eq!((response.status_code, response.body.as_slice()), (200, &b"user 42"[..]));
+        assert_eq!(send(&router, RequestBuilder::get("/api/users/abc")).status_code, 400);
+        assert_eq!(send(&router, RequestBuilder::get("/api/users")).status_code, 400);
+    }
+
+    #[test]
+    fn param_errors_name_the_parameter() {
+        let request = RequestBuilder::get("/").build();
+        assert_eq!(request.param_as::<u64>("id"), Err(ParamError::Missing("id".to_string())));
+        let router = Router::new().get("/n/:n", |request| {
+            HttpResponse::text(request.param_as::<u8>("n").unwrap_err().to_string())
+        });
+        assert_eq!(send(&router, RequestBuilder::get("/n/300")).body, b"invalid path parameter n: \"300\"");
+    }
+
+    #[test]
+    fn an_error_handler_can_map_param_errors() {
+        let router = user_router().error_handler(|error: &ParamError, _| {
+            HttpResponse::json_error(422, &error.to_string())
+        });
+        assert_eq!(send(&router, RequestBuilder::get("/api/users/abc")).status_code, 422);
+    }
+
+    #[test]
+    fn a_rewrite_strips_the_base_path_before_routing() {
+        let router = Router::new()
+            .rewrite(|path| {
+                if let Some(rest) = path.strip_prefix("/v1")
+                    && (rest.is_empty() || rest.starts_with('/'))
+                {
+                    *path = if rest.is_empty() { "/".to_string() } else { rest.to_string() };
+                }
+            })
+            .get("/api/health", |request| HttpResponse::text(request.raw_target.clone()));
+        let response = send(&router, RequestBuilder::get("/v1/api/health"));
+        assert_eq!((response.status_code, response.body.as_slice()), (200, &b"/v1/api/health"[..]));
+        assert_eq!(send(&router, RequestBuilder::get("/api/health")).status_code, 200);
+        assert_eq!(send(&router, RequestBuilder::get("/v1x/api/health")).status_code, 404);
+    }
+
+    #[test]
+    fn error_pages_render_by_code_then_class() {
+        let router = Router::new()
+            .error_page("4xx", "<p>{{status}} {{message}}</p>")
+            .error_page("5xx", "<p>server {{status}}</p>")
+            .error_page("418", "<p>teapot</p>")
+            .get("/fail", |_| HttpResponse::internal_server_error())
+            .get("/tea", |_| HttpResponse::new(418, "I'M A TEAPOT"))
+            .get("/api", |_| HttpResponse::json_error(404, "gone"));
+
+        let missing = send(&router, RequestBuilder::get("/missing"));
+        assert_eq!((missing.status_code, missing.body.as_slice()), (404, &b"<p>404 NOT FOUND</p>"[..]));
+        let failed = send(&router, RequestBuilder::get("/fail"));
+        assert_eq!((failed.status_code, failed.body.as_slice()), (500, &b"<p>server 500</p>"[..]));
+        assert_eq!(send(&router, RequestBuilder::get("/tea")).body, b"<p>teapot</p>");
+        // Not HTML, so an API's own error body is kept
+        assert_eq!(send(&router, RequestBuilder::get("/api")).body, br#"{"error": "gone", "status": 404}"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "4xx or 5xx")]
+    fn error_pages_need_an_error_status() {
+        let _ = Router::new().error_page("3xx", "nope");
+    }
+
+    #[test]
+    fn the_longest_prefix_owns_its_paths() {
+        let router = Router::new()
+            .proxy("/", "http://127.0.0.1:9")
+            .static_dir("/assets", std::env::temp_dir());
+
+        // Not handed on to the shorter proxy prefix, which answers every method
+        let post = send(&router, RequestBuilder::post("/assets/x"));
+        assert_eq!((post.status_code, post.header("Allow")), (405, Some("GET, HEAD, OPTIONS")));
+        let options = send(&router, RequestBuilder::new("OPTIONS", "/assets"));
+        assert_eq!((options.status_code, options.header("Allow")), (204, Some("GET, HEAD, OPTIONS")));
+        // The proxy forwards every method, OPTIONS included
+        assert_eq!(send(&router, RequestBuilder::new("OPTIONS", "/api")).status_code, 502);
+    }
+
+    fn method_echo() -> Router {
+        let echo = |request: &HttpRequest| HttpResponse::text(request.method.as_str().to_string());
+        Router::new()
+            .method_override(true)
+            .post("/items/:id", echo)
+            .put("/items/:id", echo)
+            .delete("/items/:id", echo)
+            .get("/items/:id", echo)
+            .post("/admin/users/:id/role", echo)
+            .delete("/admin/users/:id/role", echo)
+    }
+
+    fn overridden(router: &Router, path: &str, method: &str) -> Vec<u8> {
+        send(router, RequestBuilder::post(path).header("X-HTTP-Method-Override", method)).body
+    }
+
+    #[test]
+    fn overrides_outside_the_allowlist_stay_post() {
+        let router = method_echo().override_methods(&[HttpMethod::DELETE]);
+        assert_eq!(overridden(&router, "/items/1", "DELETE"), b"DELETE");
+        assert_eq!(overridden(&router, "/items/1", "PUT"), b"POST");
+        // GET is never allowlisted by default either, so a form can't fake a safe request
+        assert_eq!(overridden(&method_echo(), "/items/1", "GET"), b"POST");
+        assert_eq!(overridden(&method_echo(), "/items/1", "BOGUS"), b"POST");
+    }
+
+    #[test]
+    fn only_posts_are_overridden() {
+        let router = method_echo();
+        let request = RequestBuilder::put("/items/1").header("X-HTTP-Method-Override", "DELETE");
+        assert_eq!(send(&router, request).body, b"PUT");
+    }
+
+    #[test]
+    fn exempt_templates_match_like_routes() {
+        let router = method_echo()
+            .no_method_override("/admin/users/:id/role")
+            .no_method_override("/items/7");
+        assert_eq!(overridden(&router, "/admin/users/42/role", "DELETE"), b"POST");
+        assert_eq!(overridden(&router, "/items/7", "DELETE"), b"POST");
+        assert_eq!(overridden(&router, "/items/8", "DELETE"), b"DELETE");
+    }
+
+    #[test]
+    fn disabled_conditional_routes_are_not_registered() {
+        let router = Router::new()
+            .get_if(false, "/admin", |_| HttpResponse::text("admin".to_string()))
+            .get_if(true, "/status", |_| HttpResponse::text("up".to_string()))
+            .when(false, |router| router.post("/admin/reset", |_| HttpResponse::ok()))
+            .when(true, |router| router.delete("/cache", |_| HttpResponse::ok()));
+
+        assert_eq!(send(&router, RequestBuilder::get("/admin")).status_code, 404);
+        assert_eq!(send(&router, RequestBuilder::get("/status")).body, b"up");
+        assert_eq!(send(&router, RequestBuilder::post("/admin/reset")).status_code, 404);
+        assert_eq!(send(&router, RequestBuilder::delete("/cache")).status_code, 200);
+        assert!(router.routes().all(|(_, path)| !path.starts_with("/admin")));
     }
 }
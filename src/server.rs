@@ -0,0 +1,1126 @@
+#![allow(dead_code)]
+use crate::ThreadPool;
+use crate::access_log::{AccessLogEntry, AccessLogFormat, LogLevel};
+use crate::compression::{self, CompressionLevel, DecompressError};
+use crate::forwarded::{self, IpRange};
+use crate::headers::MediaType;
+use crate::http::{BodyError, DEFAULT_MAX_HEADER_BYTES, HeadError, HttpRequest};
+use crate::response::HttpResponse;
+use crate::router::Router;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::cell::Cell;
+use std::io::{self, BufReader, prelude::*};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// The ALPN protocols to advertise when connections arrive over TLS. The server
+// only speaks HTTP/1.x, so offering h2 would leave an h2 client sending frames
+// it can't parse; with http/1.1 alone such clients fall back cleanly. There is
+// no built-in TLS yet, so this is for whatever terminates TLS in front of it.
+pub const ALPN_PROTOCOLS: &[&[u8]] = &[b"http/1.1"];
+
+// Picks the protocol to answer a client's ALPN offer with, for a TLS
+// terminator's selection callback. None means nothing offered is spoken here,
+// which the handshake should fail with no_application_protocol rather than
+// carry on in a protocol the client didn't ask for.
+pub fn select_alpn(offered: &[&[u8]]) -> Option<&'static [u8]> {
+    ALPN_PROTOCOLS
+        .iter()
+        .copied()
+        .find(|protocol| offered.contains(protocol))
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub workers: usize,
+    // Stack size of each worker thread in bytes; None keeps the default
+    pub worker_stack_size: Option<usize>,
+    // Connections waiting for a worker before new ones get a 503
+    pub queue_bound: usize,
+    // Print one line per request in the given format
+    pub access_log: Option<AccessLogFormat>,
+    // Which responses get an access line, and whether request headers are logged too
+    pub log_level: LogLevel,
+    // Set TCP_NODELAY on accepted connections so small responses aren't delayed by Nagle
+    pub nodelay: bool,
+    // Pending connections the OS queues before accept() picks them up
+    pub backlog: i32,
+    // Open connections, queued or being handled, before new ones get a 503
+    pub max_connections: usize,
+    // Request line plus headers; a longer one, including a single header line
+    // that never ends, gets a 431. Chunked trailers get the same allowance.
+    pub max_header_bytes: usize,
+    // How long a read may block before the request is given up on with a 400
+    pub read_timeout: Option<Duration>,
+    // Time the whole request line and headers have to arrive once the first
+    // byte has, however steadily they trickle in; past it the request gets a
+    // 400 and the connection is closed
+    pub header_timeout: Option<Duration>,
+    // Largest request body, as sent, before the request gets a 413. Bodies
+    // handed to post_stream and put_stream routes aren't limited.
+    pub max_body_bytes: usize,
+    // Limits for particular Content-Types, such as ("image/*", 10 MiB), used in
+    // place of max_body_bytes. The most specific matching type wins.
+    pub body_limits: Vec<(MediaType, usize)>,
+    // Time each request has from being accepted (or, on a kept-alive
+    // connection, from arriving) to being answered, exposed to handlers as
+    // HttpRequest::deadline. Nothing is cut off when it runs out.
+    pub request_budget: Option<Duration>,
+    // Serve further HTTP/1.1 requests on the same connection unless the client
+    // sends `Connection: close`. An open connection holds on to its worker.
+    pub keep_alive: bool,
+    // How long an open connection may sit idle between requests before it's
+    // closed. read_timeout doesn't apply then, only once a request has begun.
+    pub keep_alive_timeout: Duration,
+    // Requests served on one connection before it's closed
+    pub max_keep_alive_requests: usize,
+    // Value of the Server header added to responses that don't set one; None omits it
+    pub server_header: Option<String>,
+    // Gzip textual responses for clients that send Accept-Encoding: gzip
+    pub compression: bool,
+    pub compression_level: CompressionLevel,
+    // Proxies whose X-Forwarded-For is believed when working out the client address
+    pub trusted_proxies: Vec<IpRange>,
+    // Largest body a gzip-encoded upload may inflate to before it gets a 413
+    pub max_decompressed_bytes: usize,
+    // Longest pause between retries while accept() keeps failing, e.g. out of file descriptors
+    pub accept_backoff_max: Duration,
+    // On shutdown, keep accepting for up to this long to serve connections the
+    // OS had already queued, which closing the listener would otherwise reset.
+    // Stops early once the queue is empty; None closes straight away.
+    pub shutdown_drain: Option<Duration>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            workers: 4,
+            worker_stack_size: None,
+            queue_bound: 64,
+            access_log: None,
+            log_level: LogLevel::default(),
+            nodelay: false,
+            backlog: 128,
+            max_connections: 1024,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            read_timeout: Some(Duration::from_secs(30)),
+            header_timeout: Some(Duration::from_secs(10)),
+            max_body_bytes: 10 * 1024 * 1024,
+            body_limits: Vec::new(),
+            request_budget: None,
+            keep_alive: false,
+            keep_alive_timeout: Duration::from_secs(5),
+            max_keep_alive_requests: 100,
+            server_header: Some(
+                concat!("multi-threaded-web-server/", env!("CARGO_PKG_VERSION")).to_string(),
+            ),
+            compression: false,
+            compression_level: CompressionLevel::default(),
+            trusted_proxies: Vec::new(),
+            max_decompressed_bytes: 10 * 1024 * 1024,
+            accept_backoff_max: Duration::from_secs(1),
+            shutdown_drain: None,
+        }
+    }
+}
+
+// Called once a response has been written, to record metrics or release
+// resources. It sees the outcome but can no longer change the response.
+pub type ResponseHook = Arc<dyn Fn(&Completed) + Send + Sync>;
+
+// What a ResponseHook is told about one response
+pub struct Completed<'a> {
+    // None when the request couldn't be parsed
+    pub request: Option<&'a HttpRequest>,
+    pub status: u16,
+    // Everything written for the response: head, body and any chunked framing
+    pub bytes: usize,
+    // From the request arriving, or for the first on a connection from the
+    // connection being accepted, to the response being written
+    pub duration: Duration,
+    pub timings: Timings,
+}
+
+// When each phase of one request ended, for seeing where its latency went.
+// A phase the request never reached is None; body_read is also None for a
+// streaming route, whose body is read by the handler itself.
+#[derive(Debug, Clone, Copy)]
+pub struct Timings {
+    // The same starting point as Completed::duration
+    pub started: Instant,
+    pub headers_parsed: Option<Instant>,
+    pub body_read: Option<Instant>,
+    pub handler_started: Option<Instant>,
+    pub handler_finished: Option<Instant>,
+    pub written: Option<Instant>,
+}
+
+impl Timings {
+    fn new(started: Instant) -> Self {
+        Timings {
+            started,
+            headers_parsed: None,
+            body_read: None,
+            handler_started: None,
+            handler_finished: None,
+            written: None,
+        }
+    }
+}
+
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    router: Arc<RwLock<Arc<Router>>>,
+    config: Arc<ServerConfig>,
+    pool: ThreadPool,
+    shutdown: Arc<AtomicBool>,
+    // Set by ShutdownHandle::drain: open connections close after their current request
+    draining: Arc<AtomicBool>,
+    open_connections: Arc<AtomicUsize>,
+    // Connections taken off the listeners so far
+    accepted: Arc<AtomicUsize>,
+    on_response: Option<ResponseHook>,
+}
+
+// Delays that double after each consecutive failure, up to a cap
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            next: initial.min(max),
+        }
+    }
+
+    // How long to wait after another failure
+    pub fn failure(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = self.next.saturating_mul(2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.initial.min(self.max);
+    }
+}
+
+// Counts a connection as open until the job handling it is dropped
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Stops a running server's accept loop from any thread
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<AtomicBool>,
+    draining: Arc<AtomicBool>,
+    open_connections: Arc<AtomicUsize>,
+    addrs: Vec<SocketAddr>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake each accept loop, which is blocked until the next connection arrives
+        for addr in &self.addrs {
+            let _ = TcpStream::connect(connectable(*addr));
+        }
+    }
+
+    // Stops accepting like shutdown, and also has every open connection close
+    // once its current request is answered instead of waiting for another.
+    // An idle kept-alive connection still waits out keep_alive_timeout.
+    pub fn drain(&self) -> Drain {
+        self.draining.store(true, Ordering::SeqCst);
+        self.shutdown();
+        Drain {
+            open_connections: Arc::clone(&self.open_connections),
+        }
+    }
+}
+
+// A drain in progress, done once the last open connection has closed
+pub struct Drain {
+    open_connections: Arc<AtomicUsize>,
+}
+
+impl Drain {
+    pub fn is_done(&self) -> bool {
+        self.open_connections.load(Ordering::SeqCst) == 0
+    }
+
+    // Waits up to `timeout` for the drain to finish; false if connections are still open
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.is_done() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+}
+
+// Readiness probe: confirms each accept loop still picks up new connections,
+// which catches a wedged acceptor that the OS backlog would otherwise hide
+#[derive(Clone)]
+pub struct HealthCheck {
+    addrs: Vec<SocketAddr>,
+    accepted: Arc<AtomicUsize>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl HealthCheck {
+    pub fn is_healthy(&self, timeout: Duration) -> bool {
+        if self.shutdown.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        self.addrs.iter().all(|addr| {
+            let before = self.accepted.load(Ordering::SeqCst);
+            let deadline = Instant::now() + timeout;
+            let Ok(_probe) = TcpStream::connect_timeout(&connectable(*addr), timeout) else {
+                return false;
+            };
+            while self.accepted.load(Ordering::SeqCst) == before {
+                if Instant::now() >= deadline {
+                    return false;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            true
+        })
+    }
+}
+
+// A wildcard address can't be connected to everywhere, but loopback can
+fn connectable(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        let loopback = match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        };
+        addr.set_ip(loopback);
+    }
+    addr
+}
+
+// Swaps the router of a running server. Requests already being handled finish
+// on the router they started with; later ones use the new one.
+#[derive(Clone)]
+pub struct RouterHandle {
+    router: Arc<RwLock<Arc<Router>>>,
+}
+
+impl RouterHandle {
+    pub fn replace(&self, router: Router) {
+        *self.router.write().unwrap() = Arc::new(router);
+    }
+
+    pub fn current(&self) -> Arc<Router> {
+        Arc::clone(&self.router.read().unwrap())
+    }
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, router: Router, config: ServerConfig) -> io::Result<Server> {
+        Self::bind_all(&[addr], router, config)
+    }
+
+    // Listens on every address, e.g. an IPv4 and an IPv6 one, with all of them
+    // sharing one router and thread pool
+    pub fn bind_all<A: ToSocketAddrs>(
+        addrs: &[A],
+        router: Router,
+        config: ServerConfig,
+    ) -> io::Result<Server> {
+        if addrs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind"));
+        }
+        let listeners = addrs
+            .iter()
+            .map(|addr| listen(addr, config.backlog))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut pool = ThreadPool::builder()
+            .size(config.workers)
+            .queue_bound(config.queue_bound)
+            .thread_name("http-worker");
+        if let Some(stack_size) = config.worker_stack_size {
+            pool = pool.stack_size(stack_size);
+        }
+        let pool = pool
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+
+        Ok(Server {
+            listeners,
+            router: Arc::new(RwLock::new(Arc::new(router))),
+            pool,
+            config: Arc::new(config),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            draining: Arc::new(AtomicBool::new(false)),
+            open_connections: Arc::new(AtomicUsize::new(0)),
+            accepted: Arc::new(AtomicUsize::new(0)),
+            on_response: None,
+        })
+    }
+
+    // Calls `hook` after every response is written, including error responses
+    pub fn on_response<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Completed) + Send + Sync + 'static,
+    {
+        self.on_response = Some(Arc::new(hook));
+        self
+    }
+
+    // The address of the first listener
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    pub fn shutdown_handle(&self) -> io::Result<ShutdownHandle> {
+        Ok(ShutdownHandle {
+            shutdown: Arc::clone(&self.shutdown),
+            draining: Arc::clone(&self.draining),
+            open_connections: Arc::clone(&self.open_connections),
+            addrs: self.local_addrs()?,
+        })
+    }
+
+    pub fn health_check(&self) -> io::Result<HealthCheck> {
+        Ok(HealthCheck {
+            addrs: self.local_addrs()?,
+            accepted: Arc::clone(&self.accepted),
+            shutdown: Arc::clone(&self.shutdown),
+        })
+    }
+
+    pub fn router_handle(&self) -> RouterHandle {
+        RouterHandle {
+            router: Arc::clone(&self.router),
+        }
+    }
+
+    // Serves connections until shut down; dropping the pool afterwards
+    // waits for in-flight requests to finish
+    pub fn run(self) -> io::Result<()> {
+        self.router_handle().current().check()?;
+
+        // One accept loop per listener, the first on this thread
+        thread::scope(|scope| {
+            for listener in &self.listeners[1..] {
+                scope.spawn(|| self.accept_loop(listener));
+            }
+            self.accept_loop(&self.listeners[0]);
+        });
+
+        Ok(())
+    }
+
+    fn accept_loop(&self, listener: &TcpListener) {
+        let mut backoff = Backoff::new(Duration::from_millis(5), self.config.accept_backoff_max);
+
+        // The connection that noticed the shutdown may be a real client
+        let mut pending = None;
+        for stream in listener.incoming() {
+            if self.shutdown.load(Ordering::SeqCst) {
+                pending = stream.ok();
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // Retrying at once would spin while the cause, such as fd exhaustion, persists
+                    let delay = backoff.failure();
+                    warn_event!("Failed to accept connection: {e}; retrying in {delay:?}");
+                    thread::sleep(delay);
+                    continue;
+                }
+            };
+            backoff.reset();
+            self.serve(stream);
+        }
+
+        if let Some(grace) = self.config.shutdown_drain {
+            self.drain(listener, pending, grace);
+        }
+    }
+
+    // Serves what's left in the accept queue, without waiting for more, until
+    // it's empty or `grace` runs out. A queued client is answered rather than
+    // reset, at the cost of shutdown taking up to `grace` longer.
+    fn drain(&self, listener: &TcpListener, pending: Option<TcpStream>, grace: Duration) {
+        if let Some(stream) = pending {
+            self.serve(stream);
+        }
+        if let Err(e) = listener.set_nonblocking(true) {
+            warn_event!("Failed to drain accept queue: {e}");
+            return;
+        }
+
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            match listener.accept() {
+                // Accepted sockets are read with blocking timeouts like any other
+                Ok((stream, _)) => match stream.set_nonblocking(false) {
+                    Ok(()) => self.serve(stream),
+                    Err(e) => warn_event!("Failed to accept connection: {e}"),
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                // WouldBlock once the queue is empty
+                Err(_) => break,
+            }
+        }
+    }
+
+    // Hands an accepted connection to the pool, or answers it with a 503 if
+    // there's no room
+    fn serve(&self, stream: TcpStream) {
+        self.accepted.fetch_add(1, Ordering::SeqCst);
+        if self.config.nodelay
+            && let Err(e) = stream.set_nodelay(true)
+        {
+            warn_event!("Failed to set TCP_NODELAY: {e}");
+        }
+
+        if self.open_connections.fetch_add(1, Ordering::SeqCst) >= self.config.max_connections {
+            self.open_connections.fetch_sub(1, Ordering::SeqCst);
+            warn_event!("Connection limit of {} reached", self.config.max_connections);
+            self.reject(stream);
+            return;
+        }
+        let guard = ConnectionGuard(Arc::clone(&self.open_connections));
+        let accepted = Instant::now();
+
+        let routers = self.router_handle();
+        let config = Arc::clone(&self.config);
+        let on_response = self.on_response.clone();
+        let draining = Arc::clone(&self.draining);
+        // Kept back so an overloaded pool can still answer the client
+        let overflow = stream.try_clone();
+
+        let queued = self.pool.try_execute(move || {
+            let _guard = guard;
+            handle_connection(stream, accepted, &routers, &config, on_response.as_ref(), &draining);
+        });
+
+        if queued.is_err() {
+            warn_event!(
+                "Queue full; dropped connections so far: {}",
+                self.pool.stats().dropped
+            );
+            if let Ok(stream) = overflow {
+                self.reject(stream);
+            }
+        }
+    }
+
+    // Answers a connection the server has no capacity for with a 503
+    fn reject(&self, mut stream: TcpStream) {
+        let response = self.router_handle().current().apply_default_headers(
+            HttpResponse::service_unavailable()
+                .with_body("<h1>503 - Service Unavailable</h1>".to_string()),
+        );
+        let response = with_server_header(response, &self.config);
+        let _ = stream.write_all(&response.to_bytes());
+    }
+}
+
+// Like TcpListener::bind, trying each resolved address in turn, but with a
+// configurable listen backlog
+fn listen<A: ToSocketAddrs>(addr: A, backlog: i32) -> io::Result<TcpListener> {
+    let mut last_error = None;
+
+    for addr in addr.to_socket_addrs()? {
+        let bound = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))
+            .and_then(|socket| {
+                #[cfg(unix)]
+                socket.set_reuse_address(true)?;
+                // Leave IPv4 to its own listener so "0.0.0.0" and "[::]" can share a port
+                if addr.is_ipv6() {
+                    socket.set_only_v6(true)?;
+                }
+                socket.bind(&addr.into())?;
+                socket.listen(backlog)?;
+                Ok(socket)
+            });
+
+        match bound {
+            Ok(socket) => return Ok(socket.into()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+    }))
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    accepted: Instant,
+    routers: &RouterHandle,
+    config: &ServerConfig,
+    on_response: Option<&ResponseHook>,
+    draining: &AtomicBool,
+) {
+    let peer = stream.peer_addr().ok();
+    // Writes the response, then logs and reports it with the bytes that went
+    // out. False if the client went away before it was all written.
+    let send = |router: &Router,
+                request: Option<&HttpRequest>,
+                response: &HttpResponse,
+                mut timings: Timings| {
+        let mut writer = CountingWriter {
+            inner: &stream,
+            written: 0,
+        };
+        let delivered = match response.write_to(&mut writer) {
+            Ok(()) => true,
+            Err(e) => {
+                warn_event!("Failed to write response: {e}");
+                false
+            }
+        };
+        timings.written = Some(Instant::now());
+        if let Some(metrics) = router.metrics_handle() {
+            metrics.record(response.status_code);
+        }
+        log_access(config, peer, request, response, writer.written);
+        if let Some(hook) = on_response {
+            hook(&Completed {
+                request,
+                status: response.status_code,
+                bytes: writer.written,
+                duration: timings.started.elapsed(),
+                timings,
+            });
+        }
+        delivered
+    };
+    if let Err(e) = stream.set_read_timeout(config.read_timeout) {
+        warn_event!("Failed to set read timeout: {e}");
+    }
+    // Counted against the router in use when the connection was picked up
+    let first_router = routers.current();
+    let _open = first_router.metrics_handle().map(|metrics| metrics.connection());
+    let head_deadline = Cell::new(None);
+    // Shared across requests so bytes of a pipelined request aren't lost
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: &stream,
+        deadline: &head_deadline,
+        read_timeout: config.read_timeout,
+    });
+    let mut served = 0;
+
+    loop {
+        let set_timeout = |timeout| {
+            if let Err(e) = stream.set_read_timeout(timeout) {
+                warn_event!("Failed to set read timeout: {e}");
+            }
+        };
+        if served > 0 {
+            set_timeout(Some(config.keep_alive_timeout));
+        }
+        // The client closed the connection or sent nothing before the timeout
+        if !reader.fill_buf().is_ok_and(|buffer| !buffer.is_empty()) {
+            return;
+        }
+        if served > 0 {
+            set_timeout(config.read_timeout);
+        }
+        head_deadline.set(config.header_timeout.map(|timeout| Instant::now() + timeout));
+        // Time spent queued for a worker counts against the first request's budget
+        let started = if served == 0 { accepted } else { Instant::now() };
+        let mut timings = Timings::new(started);
+        served += 1;
+        // Taken per request, so a router swapped in by reload applies to the
+        // next request even on a kept-alive connection
+        let router = routers.current();
+        let router = router.as_ref();
+
+        let read = read_request(&mut reader, &stream, &head_deadline, router, config, &mut timings);
+        let mut request = match read {
+            Ok(request) => request,
+            Err(response) => {
+                // Lingering on close is bound by its own timeout instead
+                head_deadline.set(None);
+                let response = router
+                    .apply_default_headers(response)
+                    .with_header("Connection", "close");
+                let response = with_server_header(response, config);
+                if send(router, None, &response, timings) {
+                    close_connection(&stream, &mut reader);
+                }
+                return;
+            }
+        };
+        request.peer_addr = peer;
+        request.deadline = config.request_budget.map(|budget| started + budget);
+        request.client_ip = peer.map(|peer| {
+            let forwarded_for = request.headers.get("x-forwarded-for").map(String::as_str);
+            forwarded::client_ip(peer.ip(), forwarded_for, &config.trusted_proxies)
+        });
+        // HTTP/1.1 connections persist unless the client asks to close them;
+        // HTTP/1.0 ones only when it asks to keep them open
+        let http10 = request.version == "HTTP/1.0";
+        let persistent = match request.version.as_str() {
+            "HTTP/1.1" => !request.has_connection_token("close"),
+            "HTTP/1.0" => request.has_connection_token("keep-alive"),
+            _ => false,
+        };
+        let mut keep_alive = config.keep_alive
+            && persistent
+            && served < config.max_keep_alive_requests
+            && !request.framing_conflict;
+
+        let compress = |request: &HttpRequest, response| {
+            if config.compression {
+                compression::compress_response(request, response, config.compression_level)
+            } else {
+                response
+            }
+        };
+        timings.handler_started = Some(Instant::now());
+        let handled = if router.streams_body(&request) {
+            // read_request already rejected a malformed Content-Length
+            let mut body = request.body_reader(&mut reader).unwrap().max_trailer_bytes(config.max_header_bytes);
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                router.handle_streaming_then(&mut request, &mut body, &compress)
+            }));
+            timings.handler_finished = Some(Instant::now());
+            // Skip what the handler didn't read so the next request starts in the right place
+            if io::copy(&mut body, &mut io::sink()).is_err() {
+                keep_alive = false;
+            }
+            handled
+        } else {
+            let handled =
+                panic::catch_unwind(AssertUnwindSafe(|| router.handle_then(&mut request, &compress)));
+            timings.handler_finished = Some(Instant::now());
+            handled
+        };
+
+        // A drain that began while the request was handled still closes the connection
+        if draining.load(Ordering::SeqCst) {
+            keep_alive = false;
+        }
+        match handled {
+            Ok(response) => {
+                // Without chunked encoding an HTTP/1.0 client can only find the
+                // end of a stream of unknown length by the connection closing
+                if http10 && response.is_streaming() && !response.has_header("Content-Length") {
+                    keep_alive = false;
+                }
+                // Prebuilt bytes can't get the Keep-Alive header a kept-alive
+                // connection announces, so the connection ends with them
+                if response.prebuilt.is_some() {
+                    keep_alive = false;
+                }
+                let response = if keep_alive {
+                    // Tell the client how long the connection waits and how many more requests it takes
+                    let policy = format!(
+                        "timeout={}, max={}",
+                        config.keep_alive_timeout.as_secs(),
+                        config.max_keep_alive_requests - served
+                    );
+                    let response = response.with_header("Keep-Alive", &policy);
+                    if http10 {
+                        response.with_header("Connection", "keep-alive")
+                    } else {
+                        response
+                    }
+                } else {
+                    response.with_header("Connection", "close")
+                };
+                let response = with_server_header(response, config);
+                // A stream's chunks are written as they're produced, so send
+                // each at once rather than waiting to coalesce it with the next
+                if response.is_streaming()
+                    && !config.nodelay
+                    && let Err(e) = stream.set_nodelay(true)
+                {
+                    warn_event!("Failed to set TCP_NODELAY: {e}");
+                }
+                keep_alive &= send(router, Some(&request), &response, timings);
+            }
+            Err(payload) => {
+                // Answer the client, then let the pool's panic policy decide what happens
+                let response = router
+                    .apply_default_headers(
+                        HttpResponse::internal_server_error()
+                            .with_body("<h1>500 - Internal Server Error</h1>".to_string()),
+                    )
+                    .with_header("Connection", "close");
+                let response = with_server_header(response, config);
+                if send(router, Some(&request), &response, timings) {
+                    close_connection(&stream, &mut reader);
+                }
+                panic::resume_unwind(payload);
+            }
+        }
+
+        if !keep_alive {
+            close_connection(&stream, &mut reader);
+            return;
+        }
+    }
+}
+
+// How long, and for how many bytes, a closing connection keeps reading
+const LINGER_TIMEOUT: Duration = Duration::from_millis(500);
+const LINGER_BYTES: u64 = 64 * 1024;
+
+// Ends the response with a FIN, then briefly reads and discards whatever the
+// client still sends: closing a socket with unread input makes the OS reset the
+// connection, which can throw away a response the client hasn't read yet
+fn close_connection<R: Read>(stream: &TcpStream, reader: &mut R) {
+    if let Err(e) = stream.shutdown(Shutdown::Write) {
+        // Usually the client has already closed its end
+        if e.kind() != io::ErrorKind::NotConnected {
+            warn_event!("Failed to shut down connection: {e}");
+        }
+        return;
+    }
+    let _ = stream.set_read_timeout(Some(LINGER_TIMEOUT));
+    let _ = io::copy(&mut reader.take(LINGER_BYTES), &mut io::sink());
+}
+
+fn with_server_header(response: HttpResponse, config: &ServerConfig) -> HttpResponse {
+    match &config.server_header {
+        Some(server) if !response.has_header("Server") => response.with_header("Server", server),
+        _ => response,
+    }
+}
+
+// Reads the next request, answering `Expect: 100-continue` before the body is
+// read. The body is left unread for a streaming route. Err holds the response
+// to send when the request can't be served.
+#[allow(clippy::result_large_err)]
+fn read_request<R: BufRead>(
+    reader: &mut R,
+    stream: &TcpStream,
+    head_deadline: &Cell<Option<Instant>>,
+    router: &Router,
+    config: &ServerConfig,
+    timings: &mut Timings,
+) -> Result<HttpRequest, HttpResponse> {
+    let bad_request =
+        || HttpResponse::bad_request().with_body("<h1>400 - Bad Request</h1>".to_string());
+
+    let mut request = HttpRequest::read_head_limited(reader, config.max_header_bytes)
+        .map_err(|e| match e {
+            HeadError::TooLarge => HttpResponse::request_header_fields_too_large()
+                .with_body("<h1>431 - Request Header Fields Too Large</h1>".to_string()),
+            HeadError::Malformed => bad_request(),
+        })?;
+    timings.headers_parsed = Some(Instant::now());
+    // The body is only bound by read_timeout
+    head_deadline.set(None);
+    if let Err(e) = stream.set_read_timeout(config.read_timeout) {
+        warn_event!("Failed to set read timeout: {e}");
+    }
+    let too_large = || {
+        HttpResponse::payload_too_large().with_body("<h1>413 - Payload Too Large</h1>".to_string())
+    };
+    let streams = router.streams_body(&request);
+    let limit = body_limit(&request, config);
+    let not_implemented = || {
+        HttpResponse::not_implemented().with_body("<h1>501 - Not Implemented</h1>".to_string())
+    };
+    // Only chunked can be decoded; guessing at any other framing could read
+    // the next request as part of this body
+    if request.is_chunked().is_none() {
+        return Err(not_implemented());
+    }
+
+    if let Some(expect) = request.headers.get("expect") {
+        // 100-continue is the only expectation defined by HTTP/1.1
+        if !expect.eq_ignore_ascii_case("100-continue") {
+            return Err(HttpResponse::expectation_failed()
+                .with_body("<h1>417 - Expectation Failed</h1>".to_string()));
+        }
+        // Refuse a body that's declared too large before the client sends it
+        if !streams && request.content_length().is_some_and(|length| length > limit as u64) {
+            return Err(too_large());
+        }
+        let mut writer = stream;
+        writer
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .map_err(|_| bad_request())?;
+    }
+
+    if streams {
+        request.body_reader(reader).ok_or_else(bad_request)?;
+        return Ok(request);
+    }
+    request
+        .read_body_limited(reader, limit, config.max_header_bytes)
+        .map_err(|e| match e {
+            BodyError::TooLarge => too_large(),
+            BodyError::TrailersTooLarge => HttpResponse::request_header_fields_too_large()
+                .with_body("<h1>431 - Request Header Fields Too Large</h1>".to_string()),
+            BodyError::Malformed => bad_request(),
+            BodyError::Unsupported => not_implemented(),
+        })?;
+    decode_body(&mut request, config.max_decompressed_bytes)?;
+    timings.body_read = Some(Instant::now());
+    Ok(request)
+}
+
+fn body_limit(request: &HttpRequest, config: &ServerConfig) -> usize {
+    let Some(content_type) = request.content_type() else {
+        return config.max_body_bytes;
+    };
+    config
+        .body_limits
+        .iter()
+        .filter(|(pattern, _)| content_type.matches(pattern))
+        .max_by_key(|(pattern, _)| pattern.specificity())
+        .map_or(config.max_body_bytes, |(_, limit)| *limit)
+}
+
+// Hands handlers the body as sent before any Content-Encoding was applied
+#[allow(clippy::result_large_err)]
+fn decode_body(request: &mut HttpRequest, limit: usize) -> Result<(), HttpResponse> {
+    let Some(encoding) = request.headers.get("content-encoding") else {
+        return Ok(());
+    };
+    let encoding = encoding.trim().to_ascii_lowercase();
+
+    match encoding.as_str() {
+        "identity" => {}
+        "gzip" | "x-gzip" => {
+            request.body = compression::gunzip(&request.body, limit).map_err(|e| match e {
+                DecompressError::TooLarge => HttpResponse::payload_too_large()
+                    .with_body("<h1>413 - Payload Too Large</h1>".to_string()),
+                DecompressError::Invalid => HttpResponse::bad_request()
+                    .with_body("<h1>400 - Bad Request</h1>".to_string()),
+            })?;
+        }
+        _ => {
+            return Err(HttpResponse::unsupported_media_type()
+                .with_body("<h1>415 - Unsupported Media Type</h1>".to_string()));
+        }
+    }
+
+    // Describe the body as handlers now see it
+    request.headers.remove("content-encoding");
+    request
+        .headers
+        .insert("content-length".to_string(), request.body.len().to_string());
+    Ok(())
+}
+
+// Reads from the connection, failing with TimedOut once `deadline` (when set)
+// passes. Each read may block only until then, so a client sending its head a
+// byte at a time can't hold a worker for read_timeout per byte.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: &'a Cell<Option<Instant>>,
+    read_timeout: Option<Duration>,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline.get() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            let timeout = self.read_timeout.map_or(left, |timeout| timeout.min(left));
+            self.stream.set_read_timeout(Some(timeout))?;
+        }
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+// Counts what passes through, so streamed responses are measured too
+struct CountingWriter<W> {
+    inner: W,
+    written: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn log_access(
+    config: &ServerConfig,
+    peer: Option<SocketAddr>,
+    request: Option<&HttpRequest>,
+    response: &HttpResponse,
+    bytes: usize,
+) {
+    if let Some(line) = access_line(config, peer, request, response, bytes) {
+        info_event!("{}", line);
+
+        if config.log_level == LogLevel::Debug
+            && let Some(request) = request
+        {
+            let mut headers: Vec<_> = request.headers.iter().collect();
+            headers.sort();
+            for (key, value) in headers {
+                let value = match key.as_str() {
+                    "authorization" | "cookie" | "proxy-authorization" => "[redacted]",
+                    _ => value.as_str(),
+                };
+                debug_event!("    {}: {}", key, value);
+            }
+        }
+    }
+}
+
+// The access line for a response, or None when logging is off or the log
+// level leaves this status out
+fn access_line(
+    config: &ServerConfig,
+    peer: Option<SocketAddr>,
+    request: Option<&HttpRequest>,
+    response: &HttpResponse,
+    bytes: usize,
+) -> Option<String> {
+    let format = config.access_log?;
+    if !config.log_level.logs_response(response.status_code) {
+        return None;
+    }
+    let entry = AccessLogEntry {
+        peer: request
+            .and_then(|request| request.client_ip)
+            .or(peer.map(|addr| addr.ip())),
+        time: SystemTime::now(),
+        request,
+        status: response.status_code,
+        bytes,
+    };
+    Some(entry.format(format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+
+    // A connected pair: what the server accepted and the client's end
+    fn accepted_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        (accepted, client)
+    }
+
+    #[test]
+    fn nodelay_is_set_on_accepted_connections() {
+        let config = ServerConfig {
+            nodelay: true,
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", Router::new(), config).unwrap();
+        let (accepted, _client) = accepted_pair();
+        // A clone shares the socket, so it sees the option serve() sets
+        let probe = accepted.try_clone().unwrap();
+        assert!(!probe.nodelay().unwrap());
+
+        server.serve(accepted);
+        assert!(probe.nodelay().unwrap());
+    }
+
+    #[test]
+    fn nodelay_is_left_off_by_default() {
+        let server = Server::bind("127.0.0.1:0", Router::new(), ServerConfig::default()).unwrap();
+        let (accepted, _client) = accepted_pair();
+        let probe = accepted.try_clone().unwrap();
+        server.serve(accepted);
+        assert!(!probe.nodelay().unwrap());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(5), Duration::from_millis(30));
+        let delays: Vec<_> = (0..5).map(|_| backoff.failure().as_millis()).collect();
+        assert_eq!(delays, [5, 10, 20, 30, 30]);
+
+        backoff.reset();
+        assert_eq!(backoff.failure(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn backoff_never_overflows() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::MAX);
+        for _ in 0..100 {
+            backoff.failure();
+        }
+        assert_eq!(backoff.failure(), Duration::MAX);
+    }
+
+    #[test]
+    fn error_level_logs_only_server_errors() {
+        let config = ServerConfig {
+            access_log: Some(AccessLogFormat::Common),
+            log_level: LogLevel::Error,
+            ..ServerConfig::default()
+        };
+        let request = RequestBuilder::get("/").build();
+        let line = |response: &HttpResponse| access_line(&config, None, Some(&request), response, 10);
+
+        assert_eq!(line(&HttpResponse::ok()), None);
+        assert_eq!(line(&HttpResponse::not_found()), None);
+        let failed = line(&HttpResponse::internal_server_error()).unwrap();
+        assert!(failed.contains("\"GET / HTTP/1.1\" 500 10"), "{failed}");
+    }
+
+    #[test]
+    fn access_level_logs_every_response() {
+        let config = ServerConfig {
+            access_log: Some(AccessLogFormat::Common),
+            ..ServerConfig::default()
+        };
+        let request = RequestBuilder::get("/").build();
+        assert!(access_line(&config, None, Some(&request), &HttpResponse::ok(), 10).is_some());
+        let disabled = ServerConfig::default();
+        assert_eq!(access_line(&disabled, None, Some(&request), &HttpResponse::ok(), 10), None);
+    }
+
+    #[test]
+    fn alpn_settles_on_http11_even_when_h2_is_preferred() {
+        assert_eq!(select_alpn(&[b"h2", b"http/1.1"]), Some(&b"http/1.1"[..]));
+        assert_eq!(select_alpn(&[b"http/1.1"]), Some(&b"http/1.1"[..]));
+        assert_eq!(select_alpn(&[b"h2"]), None);
+        assert_eq!(select_alpn(&[]), None);
+        assert_eq!(ALPN_PROTOCOLS, [&b"http/1.1"[..]]);
+    }
+}
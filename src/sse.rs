@@ -0,0 +1,81 @@
+#![allow(dead_code)]
+use crate::response::HttpResponse;
+use std::sync::mpsc;
+
+// Server-Sent Events on top of a chunked response. The worker that writes the
+// response stays busy until every EventSender is dropped or the client goes
+// away, so each open stream takes one thread out of the pool.
+
+#[derive(Debug)]
+pub struct Disconnected;
+
+// Pushes events to the client; cloneable so several producers can share a stream
+#[derive(Clone)]
+pub struct EventSender(mpsc::Sender<Vec<u8>>);
+
+impl EventSender {
+    pub fn send(&self, data: &str) -> Result<(), Disconnected> {
+        self.send_bytes(format_event(None, data))
+    }
+
+    // An event with a name, dispatched to `addEventListener(event, ...)` in the browser
+    pub fn send_event(&self, event: &str, data: &str) -> Result<(), Disconnected> {
+        self.send_bytes(format_event(Some(event), data))
+    }
+
+    fn send_bytes(&self, bytes: Vec<u8>) -> Result<(), Disconnected> {
+        self.0.send(bytes).map_err(|_| Disconnected)
+    }
+}
+
+// The response to return from the handler, plus the sender to feed it from
+// another thread. Sends fail once the client has disconnected.
+pub fn event_stream() -> (EventSender, HttpResponse) {
+    let (sender, receiver) = mpsc::channel();
+    let response = HttpResponse::from_chunks(receiver)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        // Ask reverse proxies such as nginx not to hold events back
+        .with_header("X-Accel-Buffering", "no");
+    (EventSender(sender), response)
+}
+
+// Multi-line data becomes one `data:` line per line, as the spec requires,
+// whether lines end in CRLF, LF or a bare CR. A line break in the event name
+// would start a field of its own, so any are dropped from it.
+pub fn format_event(event: Option<&str>, data: &str) -> Vec<u8> {
+    let mut formatted = String::new();
+    if let Some(event) = event {
+        let event: String = event.chars().filter(|c| !matches!(c, '\r' | '\n')).collect();
+        formatted.push_str(&format!("event: {}\n", event));
+    }
+    for line in data.replace("\r\n", "\n").split(['\r', '\n']) {
+        formatted.push_str(&format!("data: {}\n", line));
+    }
+    formatted.push('\n');
+    formatted.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_line_of_data_gets_its_own_field() {
+        assert_eq!(format_event(None, "hello"), b"data: hello\n\n");
+        assert_eq!(format_event(None, ""), b"data: \n\n");
+        assert_eq!(
+            format_event(Some("update"), "a\r\nb\rc\nd"),
+            b"event: update\ndata: a\ndata: b\ndata: c\ndata: d\n\n"
+        );
+    }
+
+    #[test]
+    fn line_breaks_cannot_inject_fields() {
+        assert_eq!(format_event(None, "x\rdata: y"), b"data: x\ndata: data: y\n\n");
+        assert_eq!(
+            format_event(Some("a\r\nid: 7"), "x"),
+            b"event: aid: 7\ndata: x\n\n"
+        );
+    }
+}
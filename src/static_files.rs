@@ -0,0 +1,223 @@
+#![allow(dead_code)]
+use crate::compression::accepts_gzip;
+use crate::date::{format_http_date, parse_http_date};
+use crate::http::HttpRequest;
+use crate::response::HttpResponse;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// Serves `path`, preferring a precompressed `path.gz` sibling when the client
+// accepts gzip. Conditional GET is always evaluated against the original file.
+pub fn serve_file(request: &HttpRequest, path: &Path) -> HttpResponse {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return not_found(),
+    };
+
+    // HTTP dates have one-second resolution, so compare whole seconds only
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| UNIX_EPOCH + Duration::from_secs(duration.as_secs()));
+
+    let since = request
+        .headers
+        .get("if-modified-since")
+        .and_then(|value| parse_http_date(value));
+
+    let mut gzip_path = path.as_os_str().to_owned();
+    gzip_path.push(".gz");
+    let gzip_path = PathBuf::from(gzip_path);
+    let has_gzip = gzip_path.is_file();
+
+    if let (Some(modified), Some(since)) = (modified, since)
+        && modified <= since
+    {
+        let response = HttpResponse::not_modified()
+            .with_header("Last-Modified", &format_http_date(modified));
+        // The same Vary as the 200, so a cache revalidates the right variant
+        return if has_gzip {
+            response.with_vary("Accept-Encoding")
+        } else {
+            response
+        };
+    }
+
+    let precompressed = if has_gzip && accepts_gzip(request) {
+        fs::read(&gzip_path).ok()
+    } else {
+        None
+    };
+
+    let mut response = HttpResponse::ok().with_header("Content-Type", content_type(path));
+    response = match precompressed {
+        Some(contents) => response
+            .with_header("Content-Encoding", "gzip")
+            .with_bytes(contents),
+        None => match fs::read(path) {
+            Ok(contents) => response.with_bytes(contents),
+            Err(_) => return not_found(),
+        },
+    };
+
+    // Both variants can be served from this URL, so caches must key on Accept-Encoding
+    if has_gzip {
+        response = response.with_vary("Accept-Encoding");
+    }
+    if let Some(modified) = modified {
+        response = response.with_header("Last-Modified", &format_http_date(modified));
+    }
+    response
+}
+
+// Serves `relative` from inside `root`, with a directory's index.html standing
+// in for the directory. Paths that would leave `root` are a 404.
+pub fn serve_dir(request: &HttpRequest, root: &Path, relative: &str) -> HttpResponse {
+    let mut path = root.to_path_buf();
+    for component in Path::new(relative.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return not_found(),
+        }
+    }
+
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    serve_file(request, &path)
+}
+
+// Fails unless `root` is a directory this process can list, so a mistyped
+// static root is caught at startup instead of as a 404 on every request
+pub fn check_root(root: &Path) -> io::Result<()> {
+    let context = |e: io::Error| {
+        io::Error::new(e.kind(), format!("static root {}: {}", root.display(), e))
+    };
+    if !fs::metadata(root).map_err(context)?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!("static root {} is not a directory", root.display()),
+        ));
+    }
+    fs::read_dir(root).map_err(context)?;
+    Ok(())
+}
+
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("json") => "application/json; charset=utf-8",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("xml") => "application/xml; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::not_found().with_body("<h1>404 - Page Not Found</h1>".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RequestBuilder;
+    use std::time::SystemTime;
+
+    // A fresh directory under the system temp dir, unique to `name`
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "mtws-static-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn if_modified_since_matching_gets_304() {
+        let dir = scratch_dir("ims-match");
+        let path = dir.join("page.html");
+        fs::write(&path, "<h1>hi</h1>").unwrap();
+
+        let last_modified = serve_file(&RequestBuilder::get("/page.html").build(), &path)
+            .header("Last-Modified")
+            .unwrap()
+            .to_string();
+
+        let request = RequestBuilder::get("/page.html")
+            .header("If-Modified-Since", &last_modified)
+            .build();
+        let response = serve_file(&request, &path);
+        assert_eq!(response.status_code, 304);
+        assert!(response.body.is_empty());
+        assert_eq!(response.header("Last-Modified"), Some(last_modified.as_str()));
+        // Without a precompressed sibling there's only one variant to vary on
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[test]
+    fn if_modified_since_stale_gets_full_body() {
+        let dir = scratch_dir("ims-stale");
+        let path = dir.join("page.html");
+        fs::write(&path, "<h1>hi</h1>").unwrap();
+
+        let stale = format_http_date(SystemTime::now() - Duration::from_secs(24 * 3600));
+        let request = RequestBuilder::get("/page.html")
+            .header("If-Modified-Since", &stale)
+            .build();
+        let response = serve_file(&request, &path);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, b"<h1>hi</h1>");
+    }
+
+    #[test]
+    fn gzip_accepting_client_gets_the_precompressed_variant() {
+        let dir = scratch_dir("precompressed");
+        fs::write(dir.join("app.js"), "console.log(1)").unwrap();
+        fs::write(dir.join("app.js.gz"), b"\x1f\x8bfake").unwrap();
+
+        let request = RequestBuilder::get("/app.js")
+            .header("Accept-Encoding", "gzip, deflate")
+            .build();
+        let response = serve_file(&request, &dir.join("app.js"));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("application/javascript; charset=utf-8")
+        );
+        assert_eq!(response.body, b"\x1f\x8bfake");
+
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        let response = serve_file(&RequestBuilder::get("/app.js").build(), &dir.join("app.js"));
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.body, b"console.log(1)");
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        let last_modified = response.header("Last-Modified").unwrap();
+        let request = RequestBuilder::get("/app.js")
+            .header("Accept-Encoding", "gzip")
+            .header("If-Modified-Since", last_modified)
+            .build();
+        let not_modified = serve_file(&request, &dir.join("app.js"));
+        assert_eq!(not_modified.status_code, 304);
+        assert_eq!(not_modified.header("Vary"), Some("Accept-Encoding"));
+    }
+}
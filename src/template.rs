@@ -0,0 +1,91 @@
+#![allow(dead_code)]
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Replaces `{{key}}` with the HTML-escaped value of `key` and `{{{key}}}` with
+// the value as is. Unknown keys render as nothing; whitespace inside the
+// braces is ignored.
+//
+//     let vars = HashMap::from([("name", "<Bob>")]);
+//     render("<p>Hello, {{name}}!</p>", &vars) // "<p>Hello, &lt;Bob&gt;!</p>"
+pub fn render<K, V>(template: &str, vars: &HashMap<K, V>) -> String
+where
+    K: Borrow<str> + Hash + Eq,
+    V: AsRef<str>,
+{
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start..];
+
+        let (raw, open, close) = if after.starts_with("{{{") {
+            (true, "{{{", "}}}")
+        } else {
+            (false, "{{", "}}")
+        };
+        let Some(end) = after[open.len()..].find(close) else {
+            // An unclosed placeholder is left as text
+            output.push_str(after);
+            return output;
+        };
+
+        let key = after[open.len()..open.len() + end].trim();
+        if let Some(value) = vars.get(key) {
+            if raw {
+                output.push_str(value.as_ref());
+            } else {
+                output.push_str(&escape_html(value.as_ref()));
+            }
+        }
+        rest = &after[open.len() + end + close.len()..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_escaped_by_default() {
+        let vars = HashMap::from([("name", "<script>alert('x')</script>"), ("title", "Home")]);
+        let page = render("<h1>{{ title }}</h1><p>Hello, {{name}}!</p>", &vars);
+        assert_eq!(
+            page,
+            "<h1>Home</h1><p>Hello, &lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;!</p>"
+        );
+    }
+
+    #[test]
+    fn triple_braces_insert_raw_html() {
+        let vars = HashMap::from([("body", "<b>bold</b>")]);
+        assert_eq!(render("<div>{{{body}}}</div>", &vars), "<div><b>bold</b></div>");
+    }
+
+    #[test]
+    fn unknown_and_unclosed_placeholders() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        assert_eq!(render("a{{missing}}b", &vars), "ab");
+        assert_eq!(render("a{{open", &vars), "a{{open");
+    }
+}
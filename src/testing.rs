@@ -0,0 +1,182 @@
+#![allow(dead_code)]
+use crate::http::HttpRequest;
+use crate::router::Router;
+use crate::server::{Server, ServerConfig, ShutdownHandle};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::thread;
+
+// Shuts the server down and waits for it when dropped
+pub struct ShutdownGuard {
+    handle: ShutdownHandle,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ShutdownGuard {
+    pub fn handle(&self) -> &ShutdownHandle {
+        &self.handle
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        self.handle.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Starts a server on an OS-assigned loopback port so tests can make real
+// requests without fighting over a fixed port
+pub fn spawn_test_server(router: Router) -> (SocketAddr, ShutdownGuard) {
+    spawn_test_server_with(router, ServerConfig::default())
+}
+
+// Like spawn_test_server, with the given config
+pub fn spawn_test_server_with(router: Router, config: ServerConfig) -> (SocketAddr, ShutdownGuard) {
+    let server = Server::bind("127.0.0.1:0", router, config).expect("failed to bind test server");
+    spawn_server(server)
+}
+
+// Runs an already bound server in the background, for tests that need to
+// keep something of it first, such as its router_handle
+pub fn spawn_server(server: Server) -> (SocketAddr, ShutdownGuard) {
+    let addr = server.local_addr().expect("test server has no local address");
+    let handle = server.shutdown_handle().expect("test server has no local address");
+
+    let thread = thread::spawn(move || {
+        server.run().expect("test server failed");
+    });
+
+    (
+        addr,
+        ShutdownGuard {
+            handle,
+            thread: Some(thread),
+        },
+    )
+}
+
+// Builds well-formed requests for tests, filling in Content-Length from the body:
+//
+//     let request = RequestBuilder::post("/api/users")
+//         .header("Content-Type", "application/json")
+//         .body(r#"{"name": "Charlie"}"#)
+//         .build();
+pub struct RequestBuilder {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl RequestBuilder {
+    pub fn new(method: &str, path: &str) -> Self {
+        RequestBuilder {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn get(path: &str) -> Self {
+        Self::new("GET", path)
+    }
+
+    pub fn post(path: &str) -> Self {
+        Self::new("POST", path)
+    }
+
+    pub fn put(path: &str) -> Self {
+        Self::new("PUT", path)
+    }
+
+    pub fn delete(path: &str) -> Self {
+        Self::new("DELETE", path)
+    }
+
+    pub fn patch(path: &str) -> Self {
+        Self::new("PATCH", path)
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    // The request exactly as a client would send it over the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, self.path);
+        for (key, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        let sets_length = self
+            .headers
+            .iter()
+            .any(|(key, _)| {
+                key.eq_ignore_ascii_case("content-length")
+                    || key.eq_ignore_ascii_case("transfer-encoding")
+            });
+        if !self.body.is_empty() && !sets_length {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    // Runs the bytes through the same reader the server uses
+    pub fn build(&self) -> HttpRequest {
+        HttpRequest::read_from(&mut Cursor::new(self.to_bytes()))
+            .expect("RequestBuilder produced a request the parser rejected")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpMethod;
+
+    #[test]
+    fn builder_fills_in_content_length() {
+        let bytes = RequestBuilder::post("/api/users")
+            .header("Content-Type", "application/json")
+            .body(r#"{"name": "Charlie"}"#)
+            .to_bytes();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "POST /api/users HTTP/1.1\r\nContent-Type: application/json\r\n\
+             Content-Length: 19\r\n\r\n{\"name\": \"Charlie\"}"
+        );
+    }
+
+    #[test]
+    fn builder_keeps_explicit_framing() {
+        let bytes = RequestBuilder::post("/upload")
+            .header("Transfer-Encoding", "chunked")
+            .body("3\r\nabc\r\n0\r\n\r\n")
+            .to_bytes();
+        assert!(!String::from_utf8(bytes).unwrap().contains("Content-Length"));
+    }
+
+    #[test]
+    fn build_parses_into_a_request() {
+        let request = RequestBuilder::put("/api/users/1")
+            .header("X-Trace", "abc")
+            .body("payload")
+            .build();
+        assert_eq!(request.method, HttpMethod::PUT);
+        assert_eq!(request.path, "/api/users/1");
+        assert_eq!(request.headers.get("x-trace").map(String::as_str), Some("abc"));
+        assert_eq!(request.body, b"payload");
+    }
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use multi_threaded_web_server::headers::MediaType;
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::net::SocketAddr;
+
+fn upload_server() -> (SocketAddr, impl Drop) {
+    let router = Router::new().post("/upload", |request| HttpResponse::text(request.body.len().to_string()));
+    let config = ServerConfig {
+        max_body_bytes: 512,
+        body_limits: vec![
+            (MediaType::new("application", "json"), 1024),
+            (MediaType::new("image", "*"), 8 * 1024),
+        ],
+        ..ServerConfig::default()
+    };
+    spawn_test_server_with(router, config)
+}
+
+fn upload(addr: SocketAddr, content_type: &str, size: usize) -> common::Response {
+    let request = RequestBuilder::post("/upload")
+        .header("Content-Type", content_type)
+        .header("Connection", "close")
+        .body(vec![b'a'; size])
+        .to_bytes();
+    common::send(addr, &request)
+}
+
+#[test]
+fn each_content_type_gets_its_own_limit() {
+    let (addr, _server) = upload_server();
+    let size = 4 * 1024;
+    // Over the JSON limit, but within the one for images
+    assert_eq!(upload(addr, "application/json", size).status, 413);
+    let image = upload(addr, "image/png", size);
+    assert_eq!(image.status, 200);
+    assert_eq!(image.body_str(), size.to_string());
+    assert_eq!(upload(addr, "application/json; charset=utf-8", 1000).status, 200);
+}
+
+#[test]
+fn other_types_fall_back_to_the_global_limit() {
+    let (addr, _server) = upload_server();
+    assert_eq!(upload(addr, "text/plain", 512).status, 200);
+    assert_eq!(upload(addr, "text/plain", 513).status, 413);
+}
+
+#[test]
+fn chunked_bodies_are_cut_off_at_the_limit() {
+    let (addr, _server) = upload_server();
+    let mut request = b"POST /upload HTTP/1.1\r\nHost: x\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n".to_vec();
+    for _ in 0..4 {
+        request.extend_from_slice(b"200\r\n");
+        request.extend_from_slice(&[b'a'; 0x200]);
+        request.extend_from_slice(b"\r\n");
+    }
+    request.extend_from_slice(b"0\r\n\r\n");
+    assert_eq!(common::send(addr, &request).status, 413);
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+
+fn compressed_request(builder: RequestBuilder) -> Vec<u8> {
+    builder
+        .header("Accept-Encoding", "gzip")
+        .header("Connection", "close")
+        .to_bytes()
+}
+
+#[test]
+fn head_gets_the_same_headers_as_a_compressed_get() {
+    let router = Router::new().get("/page", |_| HttpResponse::text("hello, world\n".repeat(100)));
+    let config = ServerConfig {
+        compression: true,
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(router, config);
+
+    let get = common::send(addr, &compressed_request(RequestBuilder::get("/page")));
+    let head = common::send(addr, &compressed_request(RequestBuilder::new("HEAD", "/page")));
+
+    assert_eq!(get.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(head.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(head.header("Vary"), get.header("Vary"));
+    assert_eq!(head.header("Content-Length"), get.header("Content-Length"));
+    assert_eq!(get.header("Content-Length"), Some(get.body.len().to_string().as_str()));
+    assert!(head.body.is_empty());
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Work that would take a second, done in steps until the budget nearly runs out
+fn slow_router() -> Router {
+    Router::new().get("/work", |request| {
+        for step in 0..50 {
+            if request.time_left().is_some_and(|left| left < Duration::from_millis(50)) {
+                return HttpResponse::gateway_timeout().with_body(format!("stopped after {step}"));
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        HttpResponse::text("done".to_string())
+    })
+}
+
+fn get_work(config: ServerConfig) -> (common::Response, Duration) {
+    let (addr, _server) = spawn_test_server_with(slow_router(), config);
+    let started = Instant::now();
+    let response = common::send(addr, &RequestBuilder::get("/work").header("Connection", "close").to_bytes());
+    (response, started.elapsed())
+}
+
+#[test]
+fn a_handler_stops_early_as_the_deadline_nears() {
+    let (response, elapsed) = get_work(ServerConfig {
+        request_budget: Some(Duration::from_millis(300)),
+        ..ServerConfig::default()
+    });
+    assert_eq!(response.status, 504);
+    assert!(response.body_str().starts_with("stopped after "), "{}", response.body_str());
+    assert!(elapsed < Duration::from_millis(800), "took {elapsed:?}");
+}
+
+#[test]
+fn without_a_budget_there_is_no_deadline() {
+    let router = Router::new().get("/", |request| {
+        HttpResponse::text(format!("{:?}", request.deadline()))
+    });
+    let (addr, _server) = spawn_test_server_with(router, ServerConfig::default());
+    let response = common::send(addr, &RequestBuilder::get("/").header("Connection", "close").to_bytes());
+    assert_eq!(response.body_str(), "None");
+}
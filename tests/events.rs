@@ -0,0 +1,64 @@
+mod common;
+
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::sse;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn client_reads_two_events() {
+    let router = Router::new().get("/events", |_| {
+        let (events, response) = sse::event_stream();
+        thread::spawn(move || {
+            events.send("first").unwrap();
+            events.send_event("update", "second\nline").unwrap();
+        });
+        response
+    });
+    let (addr, _server) = spawn_test_server(router);
+
+    let request = RequestBuilder::get("/events").header("Connection", "close").to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("text/event-stream"));
+    assert_eq!(
+        String::from_utf8(common::dechunk(&response.body)).unwrap(),
+        "data: first\n\nevent: update\ndata: second\ndata: line\n\n"
+    );
+}
+
+#[test]
+fn an_event_arrives_before_the_stream_ends() {
+    let finished = Arc::new(AtomicBool::new(false));
+    let release = Arc::clone(&finished);
+    let router = Router::new().get("/events", move |_| {
+        let (events, response) = sse::event_stream();
+        let release = Arc::clone(&release);
+        thread::spawn(move || {
+            events.send("early").unwrap();
+            // Hold the stream open until the client has seen the event
+            while !release.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+        response
+    });
+    let (addr, _server) = spawn_test_server(router);
+
+    let mut stream = common::connect(addr);
+    stream
+        .write_all(&RequestBuilder::get("/events").header("Connection", "close").to_bytes())
+        .unwrap();
+    let mut received = Vec::new();
+    let mut buffer = [0; 1024];
+    while !received.windows(13).any(|w| w == b"data: early\n\n") {
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(read > 0, "connection closed before the event arrived");
+        received.extend_from_slice(&buffer[..read]);
+    }
+    finished.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::io::{Read, Write};
+
+fn upload_router() -> Router {
+    Router::new().post("/upload", |req| HttpResponse::text(req.body.len().to_string()))
+}
+
+fn small_body_config() -> ServerConfig {
+    ServerConfig {
+        max_body_bytes: 16,
+        ..ServerConfig::default()
+    }
+}
+
+#[test]
+fn unsupported_expectation_gets_a_417() {
+    let (addr, _server) = spawn_test_server_with(upload_router(), ServerConfig::default());
+    let request = RequestBuilder::post("/upload")
+        .header("Expect", "something-else")
+        .header("Connection", "close")
+        .body("hi")
+        .to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 417);
+}
+
+#[test]
+fn continue_is_sent_before_the_body() {
+    let (addr, _server) = spawn_test_server_with(upload_router(), small_body_config());
+    let mut stream = common::connect(addr);
+    stream
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .unwrap();
+
+    let mut interim = [0; 25];
+    stream.read_exact(&mut interim).unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+    stream.write_all(b"hello").unwrap();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    let response = common::parse(&rest);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "5");
+}
+
+#[test]
+fn oversized_declared_body_gets_a_413_without_continue() {
+    let (addr, _server) = spawn_test_server_with(upload_router(), small_body_config());
+    let raw = common::exchange(
+        addr,
+        b"POST /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 1000\r\n\r\n",
+    );
+    assert!(raw.starts_with(b"HTTP/1.1 413"), "{}", String::from_utf8_lossy(&raw));
+    assert!(!String::from_utf8_lossy(&raw).contains("100 Continue"));
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use multi_threaded_web_server::forwarded::IpRange;
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+
+fn client_ip_seen(trusted_proxies: Vec<IpRange>) -> String {
+    let router = Router::new().get("/ip", |request| {
+        HttpResponse::text(request.client_ip.map(|ip| ip.to_string()).unwrap_or_default())
+    });
+    let config = ServerConfig {
+        trusted_proxies,
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(router, config);
+    let request = RequestBuilder::get("/ip")
+        .header("X-Forwarded-For", "198.51.100.7")
+        .header("Connection", "close")
+        .to_bytes();
+    common::send(addr, &request).body_str().to_string()
+}
+
+#[test]
+fn forwarded_for_is_used_behind_a_trusted_proxy() {
+    let seen = client_ip_seen(vec![IpRange::parse("127.0.0.0/8").unwrap()]);
+    assert_eq!(seen, "198.51.100.7");
+}
+
+#[test]
+fn forwarded_for_from_an_untrusted_peer_is_ignored() {
+    let seen = client_ip_seen(vec![IpRange::parse("10.0.0.0/8").unwrap()]);
+    assert_eq!(seen, "127.0.0.1");
+}
@@ -0,0 +1,38 @@
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::{Server, ServerConfig};
+use multi_threaded_web_server::testing::spawn_server;
+use std::time::Duration;
+
+fn start() -> Server {
+    let router = Router::new().get("/", |_| HttpResponse::ok());
+    Server::bind("127.0.0.1:0", router, ServerConfig::default()).unwrap()
+}
+
+#[test]
+fn health_check_passes_while_the_server_runs() {
+    let server = start();
+    let health = server.health_check().unwrap();
+    let (_, _server) = spawn_server(server);
+    assert!(health.is_healthy(Duration::from_secs(2)));
+    // Each probe is a fresh connection, so repeated checks keep passing
+    assert!(health.is_healthy(Duration::from_secs(2)));
+}
+
+#[test]
+fn health_check_fails_once_the_server_stops() {
+    let server = start();
+    let health = server.health_check().unwrap();
+    let (_, guard) = spawn_server(server);
+    drop(guard);
+    assert!(!health.is_healthy(Duration::from_millis(200)));
+}
+
+#[test]
+fn health_check_fails_when_nothing_accepts() {
+    // Bound but never run: the OS queues the probe, yet no accept loop takes it
+    let server = start();
+    let health = server.health_check().unwrap();
+    assert!(!health.is_healthy(Duration::from_millis(200)));
+    drop(server);
+}
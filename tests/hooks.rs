@@ -0,0 +1,108 @@
+mod common;
+
+use multi_threaded_web_server::access_log::AccessLogFormat;
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::{Server, ServerConfig};
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_server};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Seen = Arc<Mutex<Vec<(Option<String>, u16, usize)>>>;
+
+// The hook runs after the response is written, so the client may see the
+// response before the hook has recorded it
+fn wait_for(seen: &Seen, count: usize) -> Vec<(Option<String>, u16, usize)> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while seen.lock().unwrap().len() < count && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    seen.lock().unwrap().clone()
+}
+
+#[test]
+fn on_response_sees_each_status() {
+    let seen: Seen = Arc::default();
+    let record = Arc::clone(&seen);
+    let router = Router::new().get("/hello", |_| HttpResponse::text("hello".to_string()));
+    let server = Server::bind("127.0.0.1:0", router, ServerConfig::default())
+        .unwrap()
+        .on_response(move |done| {
+            let path = done.request.map(|request| request.path.clone());
+            record.lock().unwrap().push((path, done.status, done.bytes));
+        });
+    let (addr, _server) = spawn_server(server);
+
+    let close = |builder: RequestBuilder| builder.header("Connection", "close").to_bytes();
+    let raw = common::exchange(addr, &close(RequestBuilder::get("/hello")));
+    assert_eq!(common::parse(&raw).status, 200);
+    // The byte count covers the whole response as the client received it
+    assert_eq!(wait_for(&seen, 1), [(Some("/hello".to_string()), 200, raw.len())]);
+
+    assert_eq!(common::send(addr, &close(RequestBuilder::get("/missing"))).status, 404);
+    let second = wait_for(&seen, 2)[1].clone();
+    assert_eq!((second.0.as_deref(), second.1), (Some("/missing"), 404));
+
+    assert_eq!(common::send(addr, b"NONSENSE\r\n\r\n").status, 400);
+    let third = wait_for(&seen, 3)[2].clone();
+    assert_eq!((third.0, third.1), (None, 400));
+}
+
+#[test]
+fn on_response_counts_the_bytes_of_a_chunked_response() {
+    let seen: Seen = Arc::default();
+    let record = Arc::clone(&seen);
+    let router = Router::new().get("/stream", |_| {
+        HttpResponse::from_chunks(vec![b"first ".to_vec(), b"second ".to_vec(), vec![b'x'; 5000]])
+    });
+    // The access log is handed the same count
+    let config = ServerConfig {
+        access_log: Some(AccessLogFormat::Common),
+        ..ServerConfig::default()
+    };
+    let server = Server::bind("127.0.0.1:0", router, config)
+        .unwrap()
+        .on_response(move |done| record.lock().unwrap().push((None, done.status, done.bytes)));
+    let (addr, _server) = spawn_server(server);
+
+    let raw = common::exchange(addr, &RequestBuilder::get("/stream").header("Connection", "close").to_bytes());
+    let response = common::parse(&raw);
+    assert_eq!(common::dechunk(&response.body).len(), 5013);
+    assert_eq!(wait_for(&seen, 1), [(None, 200, raw.len())]);
+}
+
+#[test]
+fn timings_are_populated_in_order() {
+    let timings = Arc::new(Mutex::new(Vec::new()));
+    let record = Arc::clone(&timings);
+    let router = Router::new().post("/slow", |_| {
+        thread::sleep(Duration::from_millis(20));
+        HttpResponse::ok()
+    });
+    let server = Server::bind("127.0.0.1:0", router, ServerConfig::default())
+        .unwrap()
+        .on_response(move |done| record.lock().unwrap().push((done.timings, done.duration)));
+    let (addr, _server) = spawn_server(server);
+
+    let request = RequestBuilder::post("/slow").header("Connection", "close").body("data").to_bytes();
+    assert_eq!(common::send(addr, &request).status, 200);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while timings.lock().unwrap().is_empty() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    let (t, duration) = timings.lock().unwrap()[0];
+    let phases = [
+        t.headers_parsed.unwrap(),
+        t.body_read.unwrap(),
+        t.handler_started.unwrap(),
+        t.handler_finished.unwrap(),
+        t.written.unwrap(),
+    ];
+    assert!(t.started <= phases[0]);
+    assert!(phases.windows(2).all(|pair| pair[0] <= pair[1]), "{t:?}");
+    // The handler's sleep shows up in its own phase
+    assert!(t.handler_finished.unwrap() - t.handler_started.unwrap() >= Duration::from_millis(20));
+    assert!(duration >= t.written.unwrap() - t.started);
+}
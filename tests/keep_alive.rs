@@ -0,0 +1,92 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+fn keep_alive_config() -> ServerConfig {
+    ServerConfig {
+        keep_alive: true,
+        keep_alive_timeout: Duration::from_secs(7),
+        max_keep_alive_requests: 3,
+        ..ServerConfig::default()
+    }
+}
+
+fn hello_router() -> Router {
+    Router::new().get("/", |_| HttpResponse::text("hello".to_string()))
+}
+
+#[test]
+fn keep_alive_header_reflects_the_configuration() {
+    let (addr, _server) = spawn_test_server_with(hello_router(), keep_alive_config());
+    let mut stream = common::connect(addr);
+
+    stream.write_all(&RequestBuilder::get("/").to_bytes()).unwrap();
+    let first = common::read_response(&mut stream);
+    assert_eq!(first.header("Keep-Alive"), Some("timeout=7, max=2"));
+
+    stream.write_all(&RequestBuilder::get("/").to_bytes()).unwrap();
+    let second = common::read_response(&mut stream);
+    assert_eq!(second.header("Keep-Alive"), Some("timeout=7, max=1"));
+
+    // The last request the connection takes closes it instead
+    stream.write_all(&RequestBuilder::get("/").to_bytes()).unwrap();
+    let last = common::read_response(&mut stream);
+    assert_eq!(last.header("Keep-Alive"), None);
+    assert_eq!(last.header("Connection"), Some("close"));
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn no_keep_alive_header_when_disabled() {
+    let (addr, _server) = spawn_test_server_with(hello_router(), ServerConfig::default());
+    let response = common::send(addr, &RequestBuilder::get("/").to_bytes());
+    assert_eq!(response.header("Keep-Alive"), None);
+    assert_eq!(response.header("Connection"), Some("close"));
+}
+
+#[test]
+fn http10_keep_alive_is_opt_in() {
+    let (addr, _server) = spawn_test_server_with(hello_router(), keep_alive_config());
+
+    // Without the header an HTTP/1.0 connection closes after one response
+    let raw = common::exchange(addr, b"GET / HTTP/1.0\r\n\r\n");
+    let response = common::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_ne!(response.header("Connection"), Some("keep-alive"));
+    assert_eq!(response.header("Keep-Alive"), None);
+
+    // With it the connection stays open and says so
+    let mut stream = common::connect(addr);
+    stream.write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").unwrap();
+    let first = common::read_response(&mut stream);
+    assert_eq!(first.header("Connection"), Some("keep-alive"));
+    assert_eq!(first.header("Keep-Alive"), Some("timeout=7, max=2"));
+    stream.write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").unwrap();
+    assert_eq!(common::read_response(&mut stream).body_str(), "hello");
+}
+
+#[test]
+fn an_idle_kept_alive_connection_is_closed_after_the_idle_timeout() {
+    let config = ServerConfig {
+        keep_alive: true,
+        keep_alive_timeout: Duration::from_millis(300),
+        // Far longer than the idle limit, which alone governs the wait between requests
+        read_timeout: Some(Duration::from_secs(30)),
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(hello_router(), config);
+    let mut stream = common::connect(addr);
+    stream.write_all(&RequestBuilder::get("/").to_bytes()).unwrap();
+    assert_eq!(common::read_response(&mut stream).status, 200);
+
+    let idle = Instant::now();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    let waited = idle.elapsed();
+    assert!(waited >= Duration::from_millis(250), "closed after {waited:?}");
+    assert!(waited < Duration::from_secs(5), "closed after {waited:?}");
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use multi_threaded_web_server::middleware::concurrency_limit;
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+fn hello_router() -> Router {
+    Router::new().get("/hello", |_| HttpResponse::text("hello".to_string()))
+}
+
+#[test]
+fn connections_past_the_limit_are_refused() {
+    let config = ServerConfig {
+        max_connections: 2,
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(hello_router(), config);
+
+    // Two idle connections take up the limit
+    let held: Vec<_> = (0..2).map(|_| common::connect(addr)).collect();
+    thread::sleep(Duration::from_millis(200));
+
+    let mut refused = common::connect(addr);
+    let mut raw = Vec::new();
+    refused.read_to_end(&mut raw).unwrap();
+    assert_eq!(common::parse(&raw).status, 503);
+
+    drop(held);
+    thread::sleep(Duration::from_millis(200));
+    let request = RequestBuilder::get("/hello").header("Connection", "close").to_bytes();
+    assert_eq!(common::send(addr, &request).status, 200);
+}
+
+#[test]
+fn concurrent_requests_past_the_per_ip_cap_get_429() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let release = Arc::new(AtomicBool::new(false));
+    let (counter, gate) = (Arc::clone(&started), Arc::clone(&release));
+    let router = Router::new()
+        .middleware(concurrency_limit(2))
+        .get("/slow", move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            while !gate.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            HttpResponse::text("slow".to_string())
+        })
+        .get("/hello", |_| HttpResponse::text("hello".to_string()));
+    let config = ServerConfig {
+        workers: 4,
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(router, config);
+    let request = |path| RequestBuilder::get(path).header("Connection", "close").to_bytes();
+
+    let slow: Vec<_> = (0..2)
+        .map(|_| {
+            let mut stream = common::connect(addr);
+            stream.write_all(&request("/slow")).unwrap();
+            stream
+        })
+        .collect();
+    while started.load(Ordering::SeqCst) < 2 {
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    // Both slots are taken, so a third request from the same address is refused
+    assert_eq!(common::send(addr, &request("/hello")).status, 429);
+
+    release.store(true, Ordering::SeqCst);
+    for mut stream in slow {
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).unwrap();
+        assert_eq!(common::parse(&raw).status, 200);
+    }
+    // The slots are given back once those requests finish
+    assert_eq!(common::send(addr, &request("/hello")).status, 200);
+}
+
+fn chunked_post(trailers: &str) -> Vec<u8> {
+    format!(
+        "POST /upload HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+         5\r\nhello\r\n0\r\n{}\r\n",
+        trailers
+    )
+    .into_bytes()
+}
+
+#[test]
+fn oversized_chunked_trailers_get_a_431() {
+    let router = Router::new().post("/upload", |req| HttpResponse::text(String::from_utf8_lossy(&req.body).into_owned()));
+    let (addr, _server) = spawn_test_server_with(router, ServerConfig {
+        max_header_bytes: 1024,
+        ..ServerConfig::default()
+    });
+
+    let response = common::send(addr, &chunked_post("x-note: small\r\n"));
+    assert_eq!((response.status, response.body_str()), (200, "hello"));
+
+    let response = common::send(addr, &chunked_post(&"x-pad: 0123456789\r\n".repeat(100)));
+    assert_eq!(response.status, 431);
+    let response = common::send(addr, &chunked_post(&format!("x-big: {}\r\n", "b".repeat(4096))));
+    assert_eq!(response.status, 431);
+}
+
+#[test]
+fn an_endless_chunk_extension_gets_a_400() {
+    let router = Router::new().post("/upload", |_| HttpResponse::ok());
+    let (addr, _server) = spawn_test_server_with(router, ServerConfig::default());
+
+    let mut request = b"POST /upload HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5;"
+        .to_vec();
+    request.extend(vec![b'e'; 16 * 1024]);
+    let mut stream = common::connect(addr);
+    // The server may answer and close before taking it all
+    let _ = stream.write_all(&request);
+    let mut raw = Vec::new();
+    let _ = stream.read_to_end(&mut raw);
+    assert_eq!(common::parse(&raw).status, 400);
+}
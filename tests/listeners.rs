@@ -0,0 +1,37 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::{Server, ServerConfig};
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_server};
+
+fn hello_router() -> Router {
+    Router::new().get("/hello", |_| HttpResponse::text("hello".to_string()))
+}
+
+fn get_hello() -> Vec<u8> {
+    RequestBuilder::get("/hello").header("Connection", "close").to_bytes()
+}
+
+#[test]
+fn every_bound_address_serves_requests() {
+    let server =
+        Server::bind_all(&["127.0.0.1:0", "127.0.0.1:0"], hello_router(), ServerConfig::default())
+            .unwrap();
+    let addrs = server.local_addrs().unwrap();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0].port(), addrs[1].port());
+    let (_, _server) = spawn_server(server);
+
+    for addr in addrs {
+        let response = common::send(addr, &get_hello());
+        assert_eq!(response.status, 200, "{addr}");
+        assert_eq!(response.body_str(), "hello");
+    }
+}
+
+#[test]
+fn binding_no_addresses_fails() {
+    let addrs: [&str; 0] = [];
+    assert!(Server::bind_all(&addrs, hello_router(), ServerConfig::default()).is_err());
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "log")]
+
+mod common;
+
+use log::{Level, Log, Metadata, Record};
+use multi_threaded_web_server::access_log::AccessLogFormat;
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use multi_threaded_web_server::middleware;
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+
+// Keeps every record so the test can look for the server's own
+struct TestLogger {
+    records: Mutex<Vec<(Level, String)>>,
+}
+
+impl Log for TestLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.records.lock().unwrap().push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: TestLogger = TestLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+// Tests share the one global logger, so each looks only for its own records
+fn install_logger() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+}
+
+fn wait_for_record(level: Level, matches: impl Fn(&str) -> bool) {
+    // Access lines are logged after the response is written
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let logged = LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(logged, message)| *logged == level && matches(message));
+        if logged {
+            return;
+        }
+        assert!(Instant::now() < deadline, "no matching {level} record");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn handled_requests_emit_an_info_record() {
+    install_logger();
+
+    let router = Router::new().get("/logged", |_| HttpResponse::text("hi".to_string()));
+    let config = ServerConfig {
+        access_log: Some(AccessLogFormat::Common),
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(router, config);
+    let response = common::send(addr, &RequestBuilder::get("/logged").header("Connection", "close").to_bytes());
+    assert_eq!(response.status, 200);
+
+    wait_for_record(Level::Info, |message| message.contains("GET /logged") && message.contains("200"));
+}
+
+#[test]
+fn body_log_records_body_snippets() {
+    install_logger();
+
+    let router = Router::new()
+        .middleware(middleware::body_log(16))
+        .post("/echo", |request| HttpResponse::ok().with_bytes(request.body.clone()));
+    let (addr, _server) = spawn_test_server_with(router, ServerConfig::default());
+    let request = RequestBuilder::post("/echo")
+        .header("Connection", "close")
+        .body("name=snippet&padding=0123456789")
+        .to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.body_str(), "name=snippet&padding=0123456789");
+
+    wait_for_record(Level::Debug, |message| message == r#"> POST /echo "name=snippet&pad" (+15 bytes)"#);
+    wait_for_record(Level::Debug, |message| message == r#"< 200 /echo "name=snippet&pad" (+15 bytes)"#);
+}
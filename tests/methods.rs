@@ -0,0 +1,44 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::testing::spawn_test_server;
+
+fn router() -> Router {
+    Router::new().get("/", |_| HttpResponse::ok())
+}
+
+#[test]
+fn trace_is_disabled_by_default() {
+    let (addr, _server) = spawn_test_server(router());
+    let response = common::send(addr, b"TRACE / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    assert_eq!(response.status, 405);
+}
+
+#[test]
+fn connect_is_rejected_without_a_handler() {
+    let (addr, _server) = spawn_test_server(router());
+    let request = b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nConnection: close\r\n\r\n";
+    assert_eq!(common::send(addr, request).status, 405);
+}
+
+#[test]
+fn trace_echoes_the_request_when_allowed() {
+    let (addr, _server) = spawn_test_server(router().allow_trace(true));
+    let request = b"TRACE / HTTP/1.1\r\nHost: x\r\nCookie: secret=1\r\nConnection: close\r\n\r\n";
+    let response = common::send(addr, request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("message/http"));
+    assert!(response.body_str().starts_with("TRACE / HTTP/1.1\r\n"));
+    assert!(!response.body_str().contains("secret"));
+}
+
+#[test]
+fn connect_goes_to_its_handler() {
+    let router = router().connect(|request| HttpResponse::text(format!("tunnel to {}", request.raw_target)));
+    let (addr, _server) = spawn_test_server(router);
+    let request = b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nConnection: close\r\n\r\n";
+    let response = common::send(addr, request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "tunnel to example.com:443");
+}
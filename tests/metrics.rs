@@ -0,0 +1,41 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn get(addr: std::net::SocketAddr, path: &str) -> common::Response {
+    common::send(addr, &RequestBuilder::get(path).header("Connection", "close").to_bytes())
+}
+
+#[test]
+fn metrics_reflect_a_handled_request() {
+    let router = Router::new()
+        .metrics("/metrics")
+        .get("/hello", |_| HttpResponse::text("hello".to_string()));
+    let (addr, _server) = spawn_test_server(router);
+    assert_eq!(get(addr, "/hello").status, 200);
+
+    // A response is counted once written, which may be just after the client
+    // reads it, so poll until the 2xx shows up
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let text = loop {
+        let text = get(addr, "/metrics").body_str().to_string();
+        if !text.contains("http_requests_total 0\n") || Instant::now() > deadline {
+            break text;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let counter = |name: &str| -> usize {
+        let line = text.lines().find(|line| line.starts_with(name)).unwrap();
+        line.rsplit(' ').next().unwrap().parse().unwrap()
+    };
+    // Usually exactly one; any earlier poll also counts as a 2xx
+    assert!(counter("http_requests_total ") >= 1, "{text}");
+    assert_eq!(counter("http_responses_total{class=\"2xx\"}"), counter("http_requests_total "));
+    assert_eq!(counter("http_responses_total{class=\"4xx\"}"), 0);
+    // The connection asking for the metrics is open while they're rendered
+    assert!(counter("http_open_connections ") >= 1, "{text}");
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server};
+
+// The same shape as the example's PATCH /api/users/:id
+fn users_router() -> Router {
+    Router::new()
+        .put("/api/users/:id", |_| HttpResponse::text("replaced".to_string()))
+        .patch("/api/users/:id", |req| {
+            let Some(id) = req.param("id").and_then(|id| id.parse::<u64>().ok()) else {
+                return HttpResponse::json_error(404, "no such user");
+            };
+            let changes = req.body_string().unwrap_or("").trim();
+            if !(changes.starts_with('{') && changes.ends_with('}')) {
+                return HttpResponse::json_error(400, "expected a JSON object of fields to change");
+            }
+            HttpResponse::json(200, "OK").with_body(format!(r#"{{"id": {}, "changes": {}}}"#, id, changes))
+        })
+}
+
+fn patch(body: &str, path: &str) -> common::Response {
+    let (addr, _server) = spawn_test_server(users_router());
+    let request = RequestBuilder::patch(path)
+        .header("Content-Type", "application/json")
+        .header("Connection", "close")
+        .body(body)
+        .to_bytes();
+    common::send(addr, &request)
+}
+
+#[test]
+fn patch_reaches_its_handler_with_the_partial_body() {
+    let response = patch(r#"{"email": "new@example.com"}"#, "/api/users/7");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), r#"{"id": 7, "changes": {"email": "new@example.com"}}"#);
+}
+
+#[test]
+fn patch_validates_the_id_and_body() {
+    assert_eq!(patch("{}", "/api/users/abc").status, 404);
+    assert_eq!(patch("email=x", "/api/users/7").status, 400);
+}
+
+#[test]
+fn patch_is_routed_apart_from_put() {
+    let (addr, _server) = spawn_test_server(Router::new().put("/api/users/:id", |_| HttpResponse::ok()));
+    let request = RequestBuilder::patch("/api/users/7").header("Connection", "close").body("{}").to_bytes();
+    // No PATCH route matches, so the PUT handler must not run
+    assert_eq!(common::send(addr, &request).status, 404);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn health_bytes() -> Arc<Vec<u8>> {
+    Arc::new(
+        HttpResponse::json(200, "OK")
+            .with_header("X-Prebuilt", "1")
+            .with_body(r#"{"status": "up"}"#.to_string())
+            .to_bytes(),
+    )
+}
+
+// Kept-alive and with headers the server would normally add to every response
+fn server(bytes: Arc<Vec<u8>>) -> (SocketAddr, impl Drop) {
+    let router = Router::new()
+        .default_header("X-Default", "yes")
+        .get("/health", move |_| HttpResponse::from_prebuilt(Arc::clone(&bytes)));
+    let config = ServerConfig {
+        keep_alive: true,
+        server_header: Some("test".to_string()),
+        ..ServerConfig::default()
+    };
+    spawn_test_server_with(router, config)
+}
+
+#[test]
+fn prebuilt_bytes_are_written_verbatim() {
+    let bytes = health_bytes();
+    let (addr, _server) = server(Arc::clone(&bytes));
+
+    assert_eq!(common::exchange(addr, &RequestBuilder::get("/health").to_bytes()), *bytes);
+}
+
+#[test]
+fn a_prebuilt_response_ends_a_kept_alive_connection() {
+    let bytes = health_bytes();
+    let (addr, _server) = server(Arc::clone(&bytes));
+
+    // Two pipelined requests only get the first answered before the close
+    let mut client = common::connect(addr);
+    let mut requests = RequestBuilder::get("/health").to_bytes();
+    requests.extend(RequestBuilder::get("/health").to_bytes());
+    client.write_all(&requests).unwrap();
+    let mut raw = Vec::new();
+    client.read_to_end(&mut raw).unwrap();
+    assert_eq!(raw, *bytes);
+}
+
+#[test]
+fn head_gets_the_prebuilt_head_alone() {
+    let bytes = health_bytes();
+    let (addr, _server) = server(Arc::clone(&bytes));
+
+    let raw = common::exchange(addr, &RequestBuilder::new("HEAD", "/health").to_bytes());
+    let head_end = bytes.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    assert_eq!(raw, bytes[..head_end]);
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+// Answers every request with what it received, so tests can see what was forwarded
+fn echo_upstream() -> Router {
+    Router::new().middleware(|req, _next| {
+        let forwarded_for = req.headers.get("x-forwarded-for").cloned().unwrap_or_default();
+        HttpResponse::text(format!(
+            "{} {} xff={} body={}",
+            req.method,
+            req.raw_target,
+            forwarded_for,
+            String::from_utf8_lossy(&req.body)
+        ))
+        .with_header("X-Upstream", "yes")
+    })
+}
+
+#[test]
+fn requests_under_the_prefix_reach_the_upstream() {
+    let (upstream, _upstream) = spawn_test_server(echo_upstream());
+    let front = Router::new()
+        .proxy("/api", &format!("http://{}/backend", upstream))
+        .get("/api/local", |_| HttpResponse::text("local".to_string()));
+    let (addr, _front) = spawn_test_server(front);
+
+    let request = RequestBuilder::post("/api/items%20x?page=2")
+        .header("Connection", "close")
+        .body("payload")
+        .to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("X-Upstream"), Some("yes"));
+    assert_eq!(
+        response.body_str(),
+        "POST /backend/items%20x?page=2 xff=127.0.0.1 body=payload"
+    );
+
+    // Exact routes still win over the proxy prefix
+    let request = RequestBuilder::get("/api/local").header("Connection", "close").to_bytes();
+    assert_eq!(common::send(addr, &request).body_str(), "local");
+}
+
+#[test]
+fn unreachable_upstream_gets_a_502() {
+    // A port nothing listens on once the listener is dropped
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let front = Router::new().proxy("/api", &format!("http://127.0.0.1:{}", port));
+    let (addr, _front) = spawn_test_server(front);
+
+    let request = RequestBuilder::get("/api/items").header("Connection", "close").to_bytes();
+    assert_eq!(common::send(addr, &request).status, 502);
+}
+
+#[test]
+fn a_rewritten_path_is_what_gets_forwarded() {
+    let (upstream, _upstream) = spawn_test_server(echo_upstream());
+    let front = Router::new()
+        .rewrite(|path| {
+            if let Some(rest) = path.strip_prefix("/v1") {
+                *path = rest.to_string();
+            }
+        })
+        .proxy("/api", &format!("http://{}/backend", upstream));
+    let (addr, _front) = spawn_test_server(front);
+
+    let request = RequestBuilder::get("/v1/api/items%20x?page=2").header("Connection", "close").to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "GET /backend/items%20x?page=2 xff=127.0.0.1 body=");
+}
+
+// Sends `body` the way curl does for a large upload: the head with
+// `Expect: 100-continue`, then the body once the server says to go on
+fn post_expecting_continue(addr: std::net::SocketAddr, body: &[u8]) -> common::Response {
+    let head = RequestBuilder::post("/api/upload")
+        .header("Expect", "100-continue")
+        .header("Content-Length", &body.len().to_string())
+        .header("Connection", "close")
+        .to_bytes();
+    let mut stream = common::connect(addr);
+    stream.write_all(&head).unwrap();
+
+    let mut interim = Vec::new();
+    let mut byte = [0; 1];
+    while !interim.ends_with(b"\r\n\r\n") {
+        assert_eq!(stream.read(&mut byte).unwrap(), 1, "closed before 100 Continue");
+        interim.push(byte[0]);
+    }
+    assert!(interim.starts_with(b"HTTP/1.1 100"));
+    stream.write_all(body).unwrap();
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    common::parse(&raw)
+}
+
+#[test]
+fn expect_continue_is_not_passed_upstream() {
+    let upstream = Router::new().post("/upload", |req| {
+        let expect = req.headers.get("expect").map_or("none", |value| value.as_str());
+        HttpResponse::text(format!("expect={} bytes={}", expect, req.body.len()))
+    });
+    let (upstream, _upstream) = spawn_test_server(upstream);
+    let (addr, _front) = spawn_test_server(Router::new().proxy("/api", &format!("http://{}", upstream)));
+
+    let response = post_expecting_continue(addr, &[b'x'; 4096]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "expect=none bytes=4096");
+}
+
+#[test]
+fn an_upstream_100_continue_is_not_relayed_as_the_answer() {
+    // An upstream that sends a 100 Continue whatever it's asked
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        let mut length = 0;
+        while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+            line.clear();
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let reply = format!(
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            length.to_string().len(),
+            length
+        );
+        stream.write_all(reply.as_bytes()).unwrap();
+    });
+    let (addr, _front) = spawn_test_server(Router::new().proxy("/api", &format!("http://{}", upstream)));
+
+    let response = post_expecting_continue(addr, &[b'x'; 2048]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "2048");
+}
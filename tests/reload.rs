@@ -0,0 +1,35 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::{Server, ServerConfig};
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_server};
+use std::io::Write;
+
+#[test]
+fn replaced_router_serves_later_requests_on_the_same_connection() {
+    let config = ServerConfig {
+        keep_alive: true,
+        ..ServerConfig::default()
+    };
+    let old = Router::new().get("/old", |_| HttpResponse::text("old".to_string()));
+    let server = Server::bind("127.0.0.1:0", old, config).unwrap();
+    let routers = server.router_handle();
+    let (addr, _server) = spawn_server(server);
+
+    let mut stream = common::connect(addr);
+    stream.write_all(&RequestBuilder::get("/new").to_bytes()).unwrap();
+    assert_eq!(common::read_response(&mut stream).status, 404);
+    stream.write_all(&RequestBuilder::get("/old").to_bytes()).unwrap();
+    assert_eq!(common::read_response(&mut stream).body, b"old");
+
+    routers.replace(Router::new().get("/new", |_| HttpResponse::text("new".to_string())));
+
+    stream.write_all(&RequestBuilder::get("/new").to_bytes()).unwrap();
+    let response = common::read_response(&mut stream);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"new");
+
+    let request = RequestBuilder::get("/old").header("Connection", "close").to_bytes();
+    assert_eq!(common::send(addr, &request).status, 404);
+}
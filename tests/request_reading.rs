@@ -0,0 +1,277 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server, spawn_test_server_with};
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn headers_larger_than_one_kilobyte_are_read() {
+    let router = Router::new().get("/padding", |req| {
+        let padding = req.headers.get("x-padding").map_or(0, |value| value.len());
+        HttpResponse::text(padding.to_string())
+    });
+    let (addr, _server) = spawn_test_server(router);
+
+    let padding = "p".repeat(3000);
+    let request = RequestBuilder::get("/padding")
+        .header("X-Padding", &padding)
+        .header("Connection", "close")
+        .to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "3000");
+}
+
+#[test]
+fn malformed_content_length_gets_a_400() {
+    let router = Router::new().post("/echo", |req| HttpResponse::text(req.body.len().to_string()));
+    let (addr, _server) = spawn_test_server(router);
+
+    for value in ["abc", "-5", "99999999999999999999999999"] {
+        let request = RequestBuilder::post("/echo")
+            .header("Content-Length", value)
+            .header("Connection", "close")
+            .body("hello")
+            .to_bytes();
+        assert_eq!(common::send(addr, &request).status, 400, "Content-Length: {value}");
+    }
+}
+
+#[test]
+fn non_utf8_bodies_survive_intact() {
+    let router = Router::new().post("/upload", |req| {
+        assert!(req.body_string().is_err());
+        req.body.clone()
+    });
+    let (addr, _server) = spawn_test_server(router);
+
+    let body = vec![0xff, 0xfe, 0x00, 0xc3, 0x28, b'a'];
+    let request = RequestBuilder::post("/upload")
+        .header("Content-Type", "application/octet-stream")
+        .header("Connection", "close")
+        .body(body.clone())
+        .to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, body);
+}
+
+#[test]
+fn head_without_a_terminator_is_given_up_on() {
+    let config = ServerConfig {
+        header_timeout: Some(Duration::from_millis(300)),
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(Router::new(), config);
+
+    // Every byte arrives well within read_timeout, but the head never ends
+    let mut stream = common::connect(addr);
+    let started = Instant::now();
+    stream.write_all(b"GET / HTTP/1.1\r\nX-Slow: ").unwrap();
+    let mut response = Vec::new();
+    for _ in 0..40 {
+        thread::sleep(Duration::from_millis(50));
+        if stream.write_all(b"a").is_err() {
+            break;
+        }
+        stream.set_nonblocking(true).unwrap();
+        let mut buf = [0; 1024];
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buf[..read]),
+            Err(_) => {}
+        }
+        stream.set_nonblocking(false).unwrap();
+    }
+    stream.set_nonblocking(false).unwrap();
+    let _ = stream.read_to_end(&mut response);
+
+    assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+    assert!(response.starts_with(b"HTTP/1.1 400"), "{}", String::from_utf8_lossy(&response));
+}
+
+#[test]
+fn partial_head_then_close_gets_a_400() {
+    let (addr, _server) = spawn_test_server(Router::new());
+    let mut stream = common::connect(addr);
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n").unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(common::parse(&response).status, 400);
+}
+
+#[test]
+fn unsupported_transfer_encoding_gets_a_501() {
+    let router = Router::new().post("/upload", |req| HttpResponse::text(req.body.len().to_string()));
+    let (addr, _server) = spawn_test_server(router);
+
+    let request = RequestBuilder::post("/upload")
+        .header("Transfer-Encoding", "gzip, chunked")
+        .header("Connection", "close")
+        .body("0\r\n\r\n")
+        .to_bytes();
+    assert_eq!(common::send(addr, &request).status, 501);
+}
+
+#[test]
+fn content_length_and_transfer_encoding_close_the_connection() {
+    let config = ServerConfig {
+        keep_alive: true,
+        ..ServerConfig::default()
+    };
+    let router = Router::new().post("/upload", |req| HttpResponse::text(req.body_string().unwrap().to_string()));
+    let (addr, _server) = spawn_test_server_with(router, config);
+
+    let mut stream = common::connect(addr);
+    stream
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n\
+              2\r\nab\r\n0\r\n\r\n",
+        )
+        .unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    let response = common::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert_eq!(response.body_str(), "ab");
+}
+
+#[test]
+fn a_null_byte_in_the_path_is_a_bad_request() {
+    let (addr, _server) = spawn_test_server(Router::new().get("/a", |_| HttpResponse::ok()));
+    assert_eq!(common::send(addr, b"GET /a\0/../etc HTTP/1.1\r\nHost: x\r\n\r\n").status, 400);
+    assert_eq!(common::send(addr, b"GET /a\x01/b HTTP/1.1\r\nHost: x\r\n\r\n").status, 400);
+    assert_eq!(common::send(addr, b"GET /a\x7f HTTP/1.1\r\nHost: x\r\n\r\n").status, 400);
+    assert_eq!(common::send(addr, b"GET /a?q=\x01 HTTP/1.1\r\nHost: x\r\n\r\n").status, 400);
+}
+
+#[test]
+fn a_rejected_upload_is_read_in_full_after_the_server_closes() {
+    let config = ServerConfig {
+        max_body_bytes: 1024,
+        ..ServerConfig::default()
+    };
+    let router = Router::new().post("/upload", |_| HttpResponse::ok());
+    let (addr, _server) = spawn_test_server_with(router, config);
+
+    // The server answers before reading the body, then closes with the body
+    // unread; without a lingering close that could reset the connection
+    let mut stream = common::connect(addr);
+    let request = RequestBuilder::post("/upload").body(vec![b'x'; 32 * 1024]).to_bytes();
+    stream.write_all(&request).unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).expect("connection reset before the response was read");
+    let response = common::parse(&raw);
+    assert_eq!(response.status, 413);
+    assert_eq!(response.body_str(), "<h1>413 - Payload Too Large</h1>");
+}
+
+#[test]
+fn a_closing_response_ends_with_eof() {
+    let (addr, _server) = spawn_test_server(Router::new().get("/a", |_| HttpResponse::text("a".repeat(100_000))));
+    let mut stream = common::connect(addr);
+    stream.write_all(&RequestBuilder::get("/a").header("Connection", "close").to_bytes()).unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    assert_eq!(common::parse(&raw).body.len(), 100_000);
+}
+
+#[test]
+fn an_unterminated_header_line_past_the_limit_gets_431() {
+    let config = ServerConfig {
+        max_header_bytes: 1024,
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(Router::new(), config);
+
+    // The line never ends and the client keeps the connection open, so the
+    // server has to answer as soon as the limit is reached
+    let mut stream = common::connect(addr);
+    stream.write_all(b"GET / HTTP/1.1\r\nX-Long: ").unwrap();
+    stream.write_all(&[b'a'; 4096]).unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    assert_eq!(common::parse(&raw).status, 431);
+}
+
+#[test]
+fn a_path_with_hundreds_of_escapes_gets_a_400() {
+    let (addr, _server) = spawn_test_server(Router::new().get("/:name", |_| HttpResponse::text("ok".to_string())));
+    let send = |path: &str| common::send(addr, &RequestBuilder::get(path).header("Connection", "close").to_bytes());
+
+    assert_eq!(send(&format!("/{}", "%2e".repeat(400))).status, 400);
+    assert_eq!(send("/%61%62c").status, 200);
+}
+
+#[test]
+fn a_malformed_query_escape_gets_a_400() {
+    let (addr, _server) = spawn_test_server(Router::new().get("/search", |_| HttpResponse::text("ok".to_string())));
+    let send = |target: &str| common::send(addr, &RequestBuilder::get(target).header("Connection", "close").to_bytes());
+
+    assert_eq!(send("/search?a=%zz").status, 400);
+    assert_eq!(send("/search?a=%41").status, 200);
+}
+
+#[test]
+fn a_two_kilobyte_cookie_split_across_byte_1024_is_read_whole() {
+    let router = Router::new().get("/session", |req| {
+        let cookie = req.headers.get("cookie").map_or(0, |value| value.len());
+        let after = req.headers.get("x-after-cookie").map_or("missing", |value| value.as_str());
+        HttpResponse::text(format!("{} {}", cookie, after))
+    });
+    let (addr, _server) = spawn_test_server(router);
+
+    let cookie = format!("session={}", "c".repeat(2048 - "session=".len()));
+    let request = RequestBuilder::get("/session")
+        .header("Cookie", &cookie)
+        .header("X-After-Cookie", "kept")
+        .header("Connection", "close")
+        .to_bytes();
+    assert!(request.len() > 2048);
+
+    // The first write ends just past byte 1024, mid-cookie, as a browser's
+    // request can arrive in pieces
+    let mut stream = common::connect(addr);
+    stream.write_all(&request[..1025]).unwrap();
+    stream.flush().unwrap();
+    thread::sleep(Duration::from_millis(50));
+    stream.write_all(&request[1025..]).unwrap();
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    let response = common::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "2048 kept");
+}
+
+#[test]
+fn requests_of_exactly_1024_and_1025_bytes_are_read() {
+    let router = Router::new().get("/sized", |req| {
+        HttpResponse::text(req.headers.get("cookie").map_or(0, |value| value.len()).to_string())
+    });
+    let (addr, _server) = spawn_test_server(router);
+
+    for size in [1023, 1024, 1025] {
+        let build = |cookie: &str| {
+            RequestBuilder::get("/sized")
+                .header("Cookie", cookie)
+                .header("Connection", "close")
+                .to_bytes()
+        };
+        let overhead = build("").len();
+        let cookie = "k".repeat(size - overhead);
+        let request = build(&cookie);
+        assert_eq!(request.len(), size);
+
+        let response = common::send(addr, &request);
+        assert_eq!(response.status, 200, "{size}-byte request");
+        assert_eq!(response.body_str(), cookie.len().to_string());
+    }
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server};
+
+fn close(request: RequestBuilder) -> Vec<u8> {
+    request.header("Connection", "close").to_bytes()
+}
+
+#[test]
+fn get_route_answers_head_without_a_body() {
+    let router = Router::new().get("/page", |_| {
+        HttpResponse::ok()
+            .with_header("X-Page", "1")
+            .with_body("<h1>page</h1>".to_string())
+    });
+    let (addr, _server) = spawn_test_server(router);
+
+    let get = common::send(addr, &close(RequestBuilder::get("/page")));
+    let head = common::send(addr, &close(RequestBuilder::new("HEAD", "/page")));
+    assert_eq!(head.status, 200);
+    assert!(head.body.is_empty());
+    assert_eq!(head.header("Content-Length"), Some("13"));
+    assert_eq!(head.header("Content-Length"), get.header("Content-Length"));
+    assert_eq!(head.header("X-Page"), Some("1"));
+}
+
+#[test]
+fn head_route_takes_precedence_over_get() {
+    let router = Router::new()
+        .get("/page", |_| HttpResponse::ok().with_body("get".to_string()))
+        .head("/page", |_| HttpResponse::ok().with_header("X-Head", "yes"));
+    let (addr, _server) = spawn_test_server(router);
+
+    let head = common::send(addr, &close(RequestBuilder::new("HEAD", "/page")));
+    assert_eq!(head.header("X-Head"), Some("yes"));
+}
+
+#[test]
+fn error_pages_share_the_configured_template() {
+    let router = Router::new()
+        .error_page("4xx", "<main>client error {{status}}: {{message}}</main>")
+        .error_page("5xx", "<main>server error {{status}}: {{message}}</main>")
+        .get("/broken", |_| -> HttpResponse { panic!("handler bug") });
+    let (addr, _server) = spawn_test_server(router);
+
+    let missing = common::send(addr, &close(RequestBuilder::get("/nowhere")));
+    assert_eq!(missing.status, 404);
+    assert_eq!(missing.body_str(), "<main>client error 404: NOT FOUND</main>");
+
+    let broken = common::send(addr, &close(RequestBuilder::get("/broken")));
+    assert_eq!(broken.status, 500);
+    assert_eq!(broken.body_str(), "<main>server error 500: INTERNAL SERVER ERROR</main>");
+}
+
+#[test]
+fn a_disabled_route_is_a_404_while_an_enabled_one_works() {
+    let admin_enabled = false;
+    let router = Router::new()
+        .get_if(admin_enabled, "/admin", |_| HttpResponse::text("admin".to_string()))
+        .get_if(!admin_enabled, "/public", |_| HttpResponse::text("public".to_string()));
+    let (addr, _server) = spawn_test_server(router);
+
+    assert_eq!(common::send(addr, &close(RequestBuilder::get("/admin"))).status, 404);
+    let public = common::send(addr, &close(RequestBuilder::get("/public")));
+    assert_eq!((public.status, public.body_str()), (200, "public"));
+}
+
+#[test]
+fn an_explicit_head_handler_keeps_its_content_length() {
+    let size = 1_048_576;
+    let router = Router::new()
+        .get("/video", move |_| HttpResponse::ok().with_bytes(vec![0; size]))
+        .head("/video", move |_| {
+            // Reports the size without producing the body
+            HttpResponse::ok().with_header("Content-Length", &size.to_string())
+        });
+    let (addr, _server) = spawn_test_server(router);
+
+    let head = common::send(addr, &close(RequestBuilder::new("HEAD", "/video")));
+    assert_eq!(head.status, 200);
+    assert_eq!(head.header("Content-Length"), Some("1048576"));
+    assert!(head.body.is_empty());
+    let get = common::send(addr, &close(RequestBuilder::get("/video")));
+    assert_eq!(head.header("Content-Length"), get.header("Content-Length"));
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+
+fn hello_router() -> Router {
+    Router::new().get("/hello", |_| HttpResponse::text("hello".to_string()))
+}
+
+fn get_hello(config: ServerConfig) -> common::Response {
+    let (addr, _server) = spawn_test_server_with(hello_router(), config);
+    let request = RequestBuilder::get("/hello").header("Connection", "close").to_bytes();
+    common::send(addr, &request)
+}
+
+#[test]
+fn server_header_defaults_to_the_crate_name() {
+    let response = get_hello(ServerConfig::default());
+    let server = response.header("Server").unwrap();
+    assert!(server.starts_with("multi-threaded-web-server/"), "{server}");
+}
+
+#[test]
+fn server_header_can_be_customized() {
+    let response = get_hello(ServerConfig {
+        server_header: Some("edge".to_string()),
+        ..ServerConfig::default()
+    });
+    assert_eq!(response.header("Server"), Some("edge"));
+}
+
+#[test]
+fn server_header_can_be_disabled() {
+    let response = get_hello(ServerConfig {
+        server_header: None,
+        ..ServerConfig::default()
+    });
+    assert_eq!(response.header("Server"), None);
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::{Server, ServerConfig};
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_server};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Queues a request in the accept queue, then shuts the server down before
+// its accept loop has started, so that connection is only ever seen during
+// shutdown
+fn request_queued_at_shutdown(config: ServerConfig) -> TcpStream {
+    let router = Router::new().get("/", |_| HttpResponse::text("served".to_string()));
+    let server = Server::bind("127.0.0.1:0", router, config).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut client = common::connect(addr);
+    client.write_all(&RequestBuilder::get("/").header("Connection", "close").to_bytes()).unwrap();
+    server.shutdown_handle().unwrap().shutdown();
+
+    thread::spawn(move || server.run().unwrap()).join().unwrap();
+    client
+}
+
+#[test]
+fn a_connection_queued_at_shutdown_is_served_with_a_drain() {
+    let mut client = request_queued_at_shutdown(ServerConfig {
+        shutdown_drain: Some(Duration::from_millis(500)),
+        ..ServerConfig::default()
+    });
+    let mut raw = Vec::new();
+    client.read_to_end(&mut raw).unwrap();
+    let response = common::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "served");
+}
+
+#[test]
+fn without_a_drain_a_queued_connection_gets_no_response() {
+    let mut client = request_queued_at_shutdown(ServerConfig::default());
+    let mut raw = Vec::new();
+    // Closed or reset, but never answered
+    let _ = client.read_to_end(&mut raw);
+    assert!(raw.is_empty());
+}
+
+#[test]
+fn a_drain_refuses_new_connections_and_finishes_the_current_request() {
+    let router = Router::new().get("/slow", |_| {
+        thread::sleep(Duration::from_millis(300));
+        HttpResponse::text("finished".to_string())
+    });
+    let config = ServerConfig {
+        keep_alive: true,
+        ..ServerConfig::default()
+    };
+    let server = Server::bind("127.0.0.1:0", router, config).unwrap();
+    let (addr, guard) = spawn_server(server);
+
+    let mut in_flight = common::connect(addr);
+    in_flight.write_all(&RequestBuilder::get("/slow").to_bytes()).unwrap();
+    // Let a worker pick the request up before draining
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    let drain = guard.handle().drain();
+    assert!(!drain.is_done());
+
+    // Connecting after the drain began gets no response, whether the
+    // connection is refused outright or dropped from the accept queue
+    if let Ok(mut late) = TcpStream::connect(addr) {
+        let _ = late.set_read_timeout(Some(Duration::from_secs(5)));
+        let _ = late.write_all(&RequestBuilder::get("/slow").to_bytes());
+        let mut raw = Vec::new();
+        let _ = late.read_to_end(&mut raw);
+        assert!(raw.is_empty(), "a connection made during the drain was served");
+    }
+
+    let response = common::read_response(&mut in_flight);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "finished");
+    assert_eq!(response.header("Connection"), Some("close"));
+    // Closed after the response instead of kept alive for another request
+    assert_eq!(in_flight.read(&mut [0; 1]).unwrap(), 0);
+
+    assert!(drain.wait(Duration::from_secs(5)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::{Server, ServerConfig};
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mtws-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn a_missing_static_root_fails_startup() {
+    let missing = std::env::temp_dir().join(format!("mtws-missing-{}", std::process::id()));
+    let router = Router::new().static_dir("/assets", &missing);
+    assert_eq!(router.check().unwrap_err().kind(), ErrorKind::NotFound);
+
+    let server = Server::bind("127.0.0.1:0", router, ServerConfig::default()).unwrap();
+    assert!(server.run().is_err());
+}
+
+#[test]
+fn an_existing_static_root_is_served() {
+    let root = scratch_dir("static-root");
+    fs::write(root.join("app.css"), "body {}").unwrap();
+    let router = Router::new().static_dir("/assets", &root);
+    assert!(router.check().is_ok());
+
+    let (addr, _server) = spawn_test_server(router);
+    let request = RequestBuilder::get("/assets/app.css").header("Connection", "close").to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "body {}");
+    fs::remove_dir_all(root).unwrap();
+}
+
+fn get(addr: std::net::SocketAddr, path: &str) -> common::Response {
+    common::send(addr, &RequestBuilder::get(path).header("Connection", "close").to_bytes())
+}
+
+#[test]
+fn fallback_static_serves_a_single_page_app() {
+    let root = scratch_dir("spa");
+    fs::write(root.join("index.html"), "<div id=app></div>").unwrap();
+    fs::write(root.join("app.js"), "start()").unwrap();
+    let router = Router::new()
+        .get("/api/health", |_| HttpResponse::text("healthy".to_string()))
+        .fallback_static(&root, true);
+    let (addr, _server) = spawn_test_server(router);
+
+    assert_eq!(get(addr, "/api/health").body_str(), "healthy");
+    assert_eq!(get(addr, "/app.js").body_str(), "start()");
+    let route = get(addr, "/some/spa/route");
+    assert_eq!(route.status, 200);
+    assert_eq!(route.body_str(), "<div id=app></div>");
+    // A missing file is still a 404 rather than the app
+    assert_eq!(get(addr, "/missing.js").status, 404);
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn fallback_static_without_spa_404s_unknown_paths() {
+    let root = scratch_dir("no-spa");
+    fs::write(root.join("index.html"), "home").unwrap();
+    let (addr, _server) = spawn_test_server(Router::new().fallback_static(&root, false));
+
+    assert_eq!(get(addr, "/").body_str(), "home");
+    assert_eq!(get(addr, "/some/route").status, 404);
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn static_prefixes_only_answer_get_and_head() {
+    let root = scratch_dir("static-methods");
+    fs::write(root.join("app.css"), "body {}").unwrap();
+    let router = Router::new()
+        .static_dir("/assets", &root)
+        .post("/upload", |_| HttpResponse::ok());
+    let (addr, _server) = spawn_test_server(router);
+    let send = |request: RequestBuilder| common::send(addr, &request.header("Connection", "close").to_bytes());
+
+    let options = send(RequestBuilder::new("OPTIONS", "/assets/app.css"));
+    assert_eq!(options.status, 204);
+    assert_eq!(options.header("Allow"), Some("GET, HEAD, OPTIONS"));
+
+    let post = send(RequestBuilder::post("/assets/x"));
+    assert_eq!(post.status, 405);
+    assert_eq!(post.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(send(RequestBuilder::delete("/assets/app.css")).status, 405);
+
+    let head = send(RequestBuilder::new("HEAD", "/assets/app.css"));
+    assert_eq!((head.status, head.header("Content-Length")), (200, Some("7")));
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+
+const LARGE: usize = 8 * 1024 * 1024;
+
+// The body limit is well under the upload size, which streaming routes ignore
+fn counting_server() -> (SocketAddr, impl Drop) {
+    let router = Router::new()
+        .post_stream("/upload", |_, body: &mut dyn Read| {
+            let count = io::copy(body, &mut io::sink()).unwrap();
+            HttpResponse::text(count.to_string())
+        })
+        .post_stream("/ignore", |_, _: &mut dyn Read| HttpResponse::text("skipped".to_string()))
+        .get("/next", |_| HttpResponse::text("next".to_string()));
+    let config = ServerConfig {
+        max_body_bytes: 1024,
+        keep_alive: true,
+        ..ServerConfig::default()
+    };
+    spawn_test_server_with(router, config)
+}
+
+#[test]
+fn a_large_upload_streams_to_the_handler() {
+    let (addr, _server) = counting_server();
+    let request = RequestBuilder::post("/upload")
+        .header("Connection", "close")
+        .body(vec![b'x'; LARGE])
+        .to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), LARGE.to_string());
+}
+
+#[test]
+fn a_chunked_upload_is_decoded_as_it_streams() {
+    let (addr, _server) = counting_server();
+    let mut stream = common::connect(addr);
+    stream
+        .write_all(b"POST /upload HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let chunk = vec![b'y'; 64 * 1024];
+    for _ in 0..32 {
+        stream.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).unwrap();
+        stream.write_all(&chunk).unwrap();
+        stream.write_all(b"\r\n").unwrap();
+    }
+    stream.write_all(b"0\r\n\r\n").unwrap();
+
+    let response = common::read_response(&mut stream);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), (32 * chunk.len()).to_string());
+}
+
+#[test]
+fn an_unread_body_is_skipped_before_the_next_request() {
+    let (addr, _server) = counting_server();
+    let mut stream = common::connect(addr);
+    stream
+        .write_all(&RequestBuilder::post("/ignore").body(vec![b'z'; 64 * 1024]).to_bytes())
+        .unwrap();
+    stream.write_all(&RequestBuilder::get("/next").to_bytes()).unwrap();
+
+    assert_eq!(common::read_response(&mut stream).body_str(), "skipped");
+    assert_eq!(common::read_response(&mut stream).body_str(), "next");
+}
+
+#[test]
+fn streamed_uploads_with_oversized_trailers_fail_to_read() {
+    let router = Router::new().post_stream("/upload", |_, body: &mut dyn Read| {
+        match io::copy(body, &mut io::sink()) {
+            Ok(count) => HttpResponse::text(count.to_string()),
+            Err(e) => HttpResponse::bad_request().with_body(e.to_string()),
+        }
+    });
+    let config = ServerConfig {
+        max_header_bytes: 1024,
+        ..ServerConfig::default()
+    };
+    let (addr, _server) = spawn_test_server_with(router, config);
+    let upload = |trailers: &str| {
+        let request = format!(
+            "POST /upload HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+             5\r\nhello\r\n0\r\n{}\r\n",
+            trailers
+        );
+        common::send(addr, request.as_bytes())
+    };
+
+    let response = upload("x-sum: 1\r\n");
+    assert_eq!((response.status, response.body_str()), (200, "5"));
+    let response = upload(&"x-pad: 0123456789\r\n".repeat(200));
+    assert_eq!((response.status, response.body_str()), (400, "trailers too large"));
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server};
+use std::net::TcpStream;
+
+fn hello_router() -> Router {
+    Router::new().get("/hello", |_| HttpResponse::text("hello".to_string()))
+}
+
+#[test]
+fn serves_requests_on_an_ephemeral_port() {
+    let (addr, _server) = spawn_test_server(hello_router());
+    assert!(addr.ip().is_loopback());
+    assert_ne!(addr.port(), 0);
+
+    let request = RequestBuilder::get("/hello").header("Connection", "close").to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), "hello");
+}
+
+#[test]
+fn servers_get_distinct_ports() {
+    let (first, _first) = spawn_test_server(hello_router());
+    let (second, _second) = spawn_test_server(hello_router());
+    assert_ne!(first.port(), second.port());
+
+    let request = RequestBuilder::get("/hello").header("Connection", "close").to_bytes();
+    assert_eq!(common::send(first, &request).status, 200);
+    assert_eq!(common::send(second, &request).status, 200);
+}
+
+#[test]
+fn dropping_the_guard_stops_the_server() {
+    let (addr, server) = spawn_test_server(hello_router());
+    drop(server);
+    assert!(TcpStream::connect(addr).is_err());
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use multi_threaded_web_server::compression::{CompressionLevel, gzip};
+use multi_threaded_web_server::response::HttpResponse;
+use multi_threaded_web_server::router::Router;
+use multi_threaded_web_server::server::ServerConfig;
+use multi_threaded_web_server::testing::{RequestBuilder, spawn_test_server_with};
+use std::net::SocketAddr;
+
+fn echo_server(config: ServerConfig) -> (SocketAddr, impl Drop) {
+    let router = Router::new().post("/echo", |request| HttpResponse::ok().with_bytes(request.body.clone()));
+    spawn_test_server_with(router, config)
+}
+
+fn post_encoded(addr: SocketAddr, encoding: &str, body: Vec<u8>) -> common::Response {
+    let request = RequestBuilder::post("/echo")
+        .header("Content-Encoding", encoding)
+        .header("Connection", "close")
+        .body(body)
+        .to_bytes();
+    common::send(addr, &request)
+}
+
+#[test]
+fn gzip_uploads_reach_the_handler_decompressed() {
+    let (addr, _server) = echo_server(ServerConfig::default());
+    let text = "name=Charlie&".repeat(50);
+    let response = post_encoded(addr, "gzip", gzip(text.as_bytes(), CompressionLevel::Fast));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), text);
+}
+
+#[test]
+fn uploads_inflating_past_the_limit_are_rejected() {
+    let (addr, _server) = echo_server(ServerConfig {
+        max_decompressed_bytes: 1024,
+        ..ServerConfig::default()
+    });
+    let bomb = gzip(&vec![b'a'; 64 * 1024], CompressionLevel::Best);
+    assert_eq!(post_encoded(addr, "gzip", bomb).status, 413);
+}
+
+#[test]
+fn unknown_encodings_get_a_415() {
+    let (addr, _server) = echo_server(ServerConfig::default());
+    assert_eq!(post_encoded(addr, "br", b"whatever".to_vec()).status, 415);
+}
+
+#[test]
+fn invalid_gzip_is_a_bad_request() {
+    let (addr, _server) = echo_server(ServerConfig::default());
+    assert_eq!(post_encoded(addr, "gzip", b"not gzip".to_vec()).status, 400);
+}
+
+#[test]
+fn multi_value_form_fields_reach_the_handler() {
+    let router = Router::new().post("/tags", |request| {
+        let fields = request.form_multi().unwrap_or_default();
+        HttpResponse::text(format!("{:?} {:?}", fields.get("tags"), fields.get("name")))
+    });
+    let (addr, _server) = spawn_test_server_with(router, ServerConfig::default());
+    let request = RequestBuilder::post("/tags")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Connection", "close")
+        .body("tags[]=a&tags[]=b&name=x")
+        .to_bytes();
+    let response = common::send(addr, &request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body_str(), r#"Some(["a", "b"]) Some(["x"])"#);
+}